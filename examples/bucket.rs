@@ -0,0 +1,106 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Checks the bucket method against `gmpmee_spowm_naive`, and times it against
+//! `gmpmee_spowm` for 256-bit exponents over a 3072-bit modulus.
+//!
+//! Run with `cargo run --release --example bucket [MAX_LEN]`. The lengths are
+//! the powers of two from `2^6` up to `MAX_LEN`, by default `2^14`.
+
+use gmp_mpfr_sys::gmp::{self, randstate_t};
+use gmpmee_sys::{
+    AsMpz, Mpz, SpowmStrategy, bucket_window, spowm_bucket, spowm_naive, spowm_with_strategy,
+};
+use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
+
+const MODULUS_BITLEN: usize = 3072;
+const EXPONENT_BITLEN: usize = 256;
+const CHECKS: usize = 20;
+
+fn random(state: &mut randstate_t, bits: usize) -> Mpz {
+    let mut z = Mpz::new();
+    unsafe { gmp::mpz_urandomb(z.as_raw_mut(), state, bits as _) };
+    z
+}
+
+/// Returns the time of `f` and its result.
+fn time<F: FnOnce(&mut Mpz)>(f: F) -> (Duration, Mpz) {
+    let mut result = Mpz::new();
+    let start = Instant::now();
+    f(&mut result);
+    (start.elapsed(), result)
+}
+
+fn main() {
+    let max_len = match std::env::args().nth(1) {
+        Some(arg) => arg.parse().unwrap(),
+        None => 1 << 14,
+    };
+    let mut state = unsafe {
+        let mut state = MaybeUninit::uninit();
+        gmp::randinit_default(state.as_mut_ptr());
+        state.assume_init()
+    };
+    let mut modulus = random(&mut state, MODULUS_BITLEN);
+    unsafe { gmp::mpz_setbit(modulus.as_raw_mut(), (MODULUS_BITLEN - 1) as _) };
+
+    // Differential check on short vectors with every window, including zero
+    // and short exponents.
+    for len in 0..CHECKS {
+        let bases: Vec<Mpz> = (0..len)
+            .map(|_| random(&mut state, MODULUS_BITLEN + 8))
+            .collect();
+        let exponents: Vec<Mpz> = (0..len).map(|i| random(&mut state, i * 13)).collect();
+        let mut expected = Mpz::new();
+        spowm_naive(&mut expected, &bases, &exponents, &modulus).unwrap();
+        for window in 1..=12 {
+            let mut result = Mpz::new();
+            spowm_bucket(&mut result, &bases, &exponents, &modulus, window).unwrap();
+            assert_eq!(result, expected, "len {} window {}", len, window);
+        }
+    }
+    println!("bucket method agrees with gmpmee_spowm_naive");
+
+    let bases: Vec<Mpz> = (0..max_len)
+        .map(|_| random(&mut state, MODULUS_BITLEN - 1))
+        .collect();
+    let exponents: Vec<Mpz> = (0..max_len)
+        .map(|_| random(&mut state, EXPONENT_BITLEN))
+        .collect();
+    let mut len = 1 << 6;
+    while len <= max_len {
+        let (bases, exponents) = (&bases[..len], &exponents[..len]);
+        let window = bucket_window(len, EXPONENT_BITLEN);
+        let (spowm_time, expected) = time(|rop| {
+            spowm_with_strategy(rop, bases, exponents, &modulus, &SpowmStrategy::Builtin).unwrap();
+        });
+        let (bucket_time, result) = time(|rop| {
+            spowm_bucket(rop, bases, exponents, &modulus, window).unwrap();
+        });
+        assert_eq!(result, expected);
+        println!(
+            "{:>9} bases  spowm {:>10.3?}  bucket (window {:>2}) {:>10.3?}  speedup {:.2}",
+            len,
+            spowm_time,
+            window,
+            bucket_time,
+            spowm_time.as_secs_f64() / bucket_time.as_secs_f64()
+        );
+        len *= 2;
+    }
+    unsafe { gmp::randclear(&mut state) };
+}
@@ -0,0 +1,121 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Compares the fixed base tables for 256-bit exponents over a 3072-bit
+//! modulus, by table memory.
+//!
+//! Run with `cargo run --release --example comb`.
+
+use gmp_mpfr_sys::gmp::{self, randstate_t};
+use gmpmee_sys::{AsMpz, Cost, FixedBaseMethod, FixedBaseTable, Mpz};
+use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
+
+const MODULUS_BITLEN: usize = 3072;
+const EXPONENT_BITLEN: usize = 256;
+const ROUNDS: u32 = 200;
+const METHODS: [FixedBaseMethod; 10] = [
+    FixedBaseMethod::Fpowm { block_width: 4 },
+    FixedBaseMethod::Comb {
+        teeth: 4,
+        spacing: 32,
+    },
+    FixedBaseMethod::Fpowm { block_width: 6 },
+    FixedBaseMethod::Comb {
+        teeth: 4,
+        spacing: 16,
+    },
+    FixedBaseMethod::Comb {
+        teeth: 6,
+        spacing: 22,
+    },
+    FixedBaseMethod::Fpowm { block_width: 8 },
+    FixedBaseMethod::Comb {
+        teeth: 6,
+        spacing: 11,
+    },
+    FixedBaseMethod::Comb {
+        teeth: 8,
+        spacing: 16,
+    },
+    FixedBaseMethod::Fpowm { block_width: 10 },
+    FixedBaseMethod::Comb {
+        teeth: 8,
+        spacing: 8,
+    },
+];
+
+fn random(state: &mut randstate_t, bits: usize) -> Mpz {
+    let mut z = Mpz::new();
+    unsafe { gmp::mpz_urandomb(z.as_raw_mut(), state, bits as _) };
+    z
+}
+
+/// Returns the mean time of `f` over the rounds, and its last result.
+fn time<F: FnMut() -> Mpz>(mut f: F) -> (Duration, Mpz) {
+    let start = Instant::now();
+    let mut result = Mpz::new();
+    for _ in 0..ROUNDS {
+        result = f();
+    }
+    (start.elapsed() / ROUNDS, result)
+}
+
+fn main() {
+    let mut state = unsafe {
+        let mut state = MaybeUninit::uninit();
+        gmp::randinit_default(state.as_mut_ptr());
+        state.assume_init()
+    };
+    let mut modulus = random(&mut state, MODULUS_BITLEN);
+    unsafe {
+        gmp::mpz_setbit(modulus.as_raw_mut(), (MODULUS_BITLEN - 1) as _);
+        gmp::mpz_setbit(modulus.as_raw_mut(), 0);
+    }
+    let base = random(&mut state, MODULUS_BITLEN - 1);
+    let exponent = random(&mut state, EXPONENT_BITLEN);
+    let mut expected = Mpz::new();
+    unsafe {
+        gmp::mpz_powm(
+            expected.as_raw_mut(),
+            base.as_raw(),
+            exponent.as_raw(),
+            modulus.as_raw(),
+        )
+    };
+    for method in METHODS {
+        let table = FixedBaseTable::new(&base, &modulus, method, EXPONENT_BITLEN).unwrap();
+        let cost = match method {
+            FixedBaseMethod::Fpowm { block_width } => {
+                Cost::fpowm(MODULUS_BITLEN, block_width, EXPONENT_BITLEN)
+            }
+            FixedBaseMethod::Comb { teeth, spacing } => {
+                Cost::comb(MODULUS_BITLEN, teeth, spacing, EXPONENT_BITLEN)
+            }
+        }
+        .unwrap();
+        let (elapsed, result) = time(|| table.pow(&exponent).unwrap());
+        assert_eq!(result, expected);
+        println!(
+            "{:<40} {:>8} KiB  {:>4} mul  {:>10.3?}",
+            format!("{:?}", method),
+            cost.table_bytes / 1024,
+            cost.squarings + cost.multiplications,
+            elapsed
+        );
+    }
+    unsafe { gmp::randclear(&mut state) };
+}
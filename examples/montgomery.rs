@@ -0,0 +1,96 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Compares the tables with their Montgomery counterparts.
+//!
+//! Run with `cargo run --release --example montgomery`.
+
+use gmp_mpfr_sys::gmp::{self, randstate_t};
+use gmpmee_sys::{AsMpz, FpowmTable, MontFpowmTable, MontSpowmTable, Mpz, SpowmTable};
+use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
+
+const MODULUS_BITLENS: [usize; 3] = [2048, 3072, 4096];
+const BASES: usize = 16;
+const BLOCK_WIDTH: usize = 8;
+const EXPONENT_BITLEN: usize = 256;
+const ROUNDS: u32 = 20;
+
+fn random(state: &mut randstate_t, bits: usize) -> Mpz {
+    let mut z = Mpz::new();
+    unsafe { gmp::mpz_urandomb(z.as_raw_mut(), state, bits as _) };
+    z
+}
+
+fn odd_modulus(state: &mut randstate_t, bits: usize) -> Mpz {
+    let mut z = random(state, bits);
+    unsafe {
+        gmp::mpz_setbit(z.as_raw_mut(), (bits - 1) as _);
+        gmp::mpz_setbit(z.as_raw_mut(), 0);
+    }
+    z
+}
+
+/// Returns the mean time of `f` over the rounds, and its last result.
+fn time<F: FnMut() -> Mpz>(mut f: F) -> (Duration, Mpz) {
+    let start = Instant::now();
+    let mut result = Mpz::new();
+    for _ in 0..ROUNDS {
+        result = f();
+    }
+    (start.elapsed() / ROUNDS, result)
+}
+
+fn report(name: &str, bits: usize, (mpz, mont): (Duration, Duration)) {
+    println!(
+        "{:<6} {:>5} bits  mpz {:>10.3?}  montgomery {:>10.3?}  speedup {:.2}",
+        name,
+        bits,
+        mpz,
+        mont,
+        mpz.as_secs_f64() / mont.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mut state = unsafe {
+        let mut state = MaybeUninit::uninit();
+        gmp::randinit_default(state.as_mut_ptr());
+        state.assume_init()
+    };
+    for bits in MODULUS_BITLENS {
+        let modulus = odd_modulus(&mut state, bits);
+        let bases: Vec<Mpz> = (0..BASES).map(|_| random(&mut state, bits - 1)).collect();
+        let exponents: Vec<Mpz> = (0..BASES)
+            .map(|_| random(&mut state, EXPONENT_BITLEN))
+            .collect();
+
+        let table = SpowmTable::new(&modulus, &bases, BLOCK_WIDTH).unwrap();
+        let mont = MontSpowmTable::try_from(&table).unwrap();
+        let (mpz_time, expected) = time(|| table.pow(&exponents).unwrap());
+        let (mont_time, result) = time(|| mont.pow(&exponents).unwrap());
+        assert_eq!(result, expected);
+        report("spowm", bits, (mpz_time, mont_time));
+
+        let table = FpowmTable::new(&bases[0], &modulus, BLOCK_WIDTH, EXPONENT_BITLEN).unwrap();
+        let mont = MontFpowmTable::from(&table);
+        let (mpz_time, expected) = time(|| table.pow(&exponents[0]).unwrap());
+        let (mont_time, result) = time(|| mont.pow(&exponents[0]).unwrap());
+        assert_eq!(result, expected);
+        report("fpowm", bits, (mpz_time, mont_time));
+    }
+    unsafe { gmp::randclear(&mut state) };
+}
@@ -0,0 +1,69 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Tunes the block widths of spowm on the current machine.
+//!
+//! Run with `cargo run --release --example tune [PROFILE]`. The profile is
+//! printed and, if a path is given, saved there, to be loaded with
+//! `TuningProfile::read_from` and installed with `set_tuning_profile`.
+
+use gmp_mpfr_sys::gmp::{self, randstate_ptr, randstate_t};
+use gmpmee_sys::{RandSource, TuningGrid, TuningProfile};
+use std::fs::File;
+use std::mem::MaybeUninit;
+
+struct RandState(randstate_t);
+
+unsafe impl RandSource for RandState {
+    fn as_raw_mut(&mut self) -> randstate_ptr {
+        &mut self.0
+    }
+}
+
+impl Drop for RandState {
+    fn drop(&mut self) {
+        unsafe { gmp::randclear(&mut self.0) }
+    }
+}
+
+fn print(name: &str, profile: &TuningProfile) {
+    println!("{}", name);
+    for row in profile.rows() {
+        print!("{:>6} bits:", row.modulus_bitlen);
+        for (exponent_bitlen, block_width) in &row.block_widths {
+            print!(" {}+ -> {}", exponent_bitlen, block_width);
+        }
+        match row.theoretical_from {
+            Some(bitlen) => println!(", theoretical from {}", bitlen),
+            None => println!(),
+        }
+    }
+}
+
+fn main() {
+    let mut rand = unsafe {
+        let mut state = MaybeUninit::uninit();
+        gmp::randinit_default(state.as_mut_ptr());
+        RandState(state.assume_init())
+    };
+    print("built-in", &TuningProfile::builtin());
+    let profile = TuningGrid::default().tune(&mut rand).unwrap();
+    print("tuned", &profile);
+    if let Some(path) = std::env::args().nth(1) {
+        profile.write_to(File::create(&path).unwrap()).unwrap();
+        println!("saved to {}", path);
+    }
+}
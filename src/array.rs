@@ -0,0 +1,249 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::gmpmee::{
+    gmpmee_array_alloc, gmpmee_array_alloc_init, gmpmee_array_clear_dealloc, gmpmee_array_urandomb,
+};
+use crate::mpz::{AsMpz, Mpz};
+use crate::prime::RandSource;
+use gmp_mpfr_sys::gmp::{self, mpz_t};
+use std::alloc::{Layout, handle_alloc_error};
+use std::ffi::c_ulong;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Owned array of integers, allocated with [`gmpmee_array_alloc_init`] and
+/// freed with [`gmpmee_array_clear_dealloc`].
+///
+/// The array dereferences to a slice of [`Mpz`], so it can be indexed, iterated,
+/// sliced, and passed directly as bases or exponents to the safe wrappers.
+pub struct MpzArray {
+    ptr: *mut mpz_t,
+    len: usize,
+}
+
+// The array owns its integers like a `Vec<Mpz>`.
+unsafe impl Send for MpzArray {}
+unsafe impl Sync for MpzArray {}
+
+fn check_alloc(ptr: *mut mpz_t, len: usize) {
+    if ptr.is_null() && len > 0 {
+        handle_alloc_error(Layout::array::<mpz_t>(len).unwrap());
+    }
+}
+
+impl MpzArray {
+    /// Creates an array of `len` integers with value zero.
+    pub fn new(len: usize) -> Self {
+        let ptr = unsafe { gmpmee_array_alloc_init(len) };
+        check_alloc(ptr, len);
+        Self { ptr, len }
+    }
+
+    /// Creates an array holding a copy of each integer of `values`.
+    pub fn from_slice<T: AsMpz>(values: &[T]) -> Self {
+        let mut array = Self::new(values.len());
+        for (d, s) in array.iter_mut().zip(values) {
+            unsafe { gmp::mpz_set(d.as_raw_mut(), s.as_raw()) };
+        }
+        array
+    }
+
+    /// Fills the array with random integers of at most `bits` bits with
+    /// [`gmpmee_array_urandomb`].
+    ///
+    /// The integers are only as good as `rand`: GMP's own generators are not
+    /// cryptographically secure.
+    pub fn urandomb<R: RandSource>(&mut self, rand: &mut R, bits: usize) {
+        unsafe { gmpmee_array_urandomb(self.ptr, self.len, rand.as_raw_mut(), bits as c_ulong) }
+    }
+
+    /// Returns the integers as a slice.
+    pub fn as_slice(&self) -> &[Mpz] {
+        match self.len {
+            0 => &[],
+            len => unsafe { std::slice::from_raw_parts(self.ptr as *const Mpz, len) },
+        }
+    }
+
+    /// Returns the integers as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [Mpz] {
+        match self.len {
+            0 => &mut [],
+            len => unsafe { std::slice::from_raw_parts_mut(self.ptr as *mut Mpz, len) },
+        }
+    }
+
+    /// Pointer to the first `mpz_t` of the array, valid as long as `self`.
+    pub fn as_raw(&self) -> *const mpz_t {
+        self.ptr
+    }
+
+    /// Mutable pointer to the first `mpz_t` of the array, valid as long as `self`.
+    pub fn as_raw_mut(&mut self) -> *mut mpz_t {
+        self.ptr
+    }
+}
+
+impl Deref for MpzArray {
+    type Target = [Mpz];
+
+    fn deref(&self) -> &[Mpz] {
+        self.as_slice()
+    }
+}
+
+impl DerefMut for MpzArray {
+    fn deref_mut(&mut self) -> &mut [Mpz] {
+        self.as_mut_slice()
+    }
+}
+
+impl<'a> IntoIterator for &'a MpzArray {
+    type Item = &'a Mpz;
+    type IntoIter = std::slice::Iter<'a, Mpz>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut MpzArray {
+    type Item = &'a mut Mpz;
+    type IntoIter = std::slice::IterMut<'a, Mpz>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_mut_slice().iter_mut()
+    }
+}
+
+impl FromIterator<Mpz> for MpzArray {
+    fn from_iter<I: IntoIterator<Item = Mpz>>(iter: I) -> Self {
+        let values: Vec<Mpz> = iter.into_iter().collect();
+        let len = values.len();
+        let ptr = unsafe { gmpmee_array_alloc(len) };
+        check_alloc(ptr, len);
+        for (i, z) in values.into_iter().enumerate() {
+            unsafe { ptr.add(i).write(z.into_raw()) };
+        }
+        Self { ptr, len }
+    }
+}
+
+impl From<Vec<Mpz>> for MpzArray {
+    fn from(values: Vec<Mpz>) -> Self {
+        values.into_iter().collect()
+    }
+}
+
+impl Clone for MpzArray {
+    fn clone(&self) -> Self {
+        Self::from_slice(self)
+    }
+}
+
+impl PartialEq for MpzArray {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for MpzArray {}
+
+impl fmt::Debug for MpzArray {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Drop for MpzArray {
+    fn drop(&mut self) {
+        unsafe { gmpmee_array_clear_dealloc(self.ptr, self.len) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Rng, product};
+    use crate::{SpowmTable, spowm};
+
+    #[test]
+    fn empty() {
+        for array in [
+            MpzArray::new(0),
+            MpzArray::from_slice(&[] as &[Mpz]),
+            MpzArray::from_iter(std::iter::empty()),
+        ] {
+            assert!(array.is_empty());
+            assert_eq!(array.as_slice(), &[] as &[Mpz]);
+            assert_eq!(array.clone(), array);
+            assert_eq!(format!("{:?}", array), "[]");
+        }
+        let mut array = MpzArray::new(0);
+        assert!(array.as_mut_slice().is_empty());
+        array.urandomb(&mut Rng::new(7), 64);
+    }
+
+    #[test]
+    fn from_iter_and_index() {
+        let values: Vec<Mpz> = (0..10).map(|i| Mpz::from(i * i - 20)).collect();
+        let mut array: MpzArray = values.iter().cloned().collect();
+        assert_eq!(array.len(), 10);
+        assert_eq!(array.as_slice(), &values[..]);
+        assert_eq!(array[3], Mpz::from(-11));
+        array[3] = Mpz::from(1u64 << 40);
+        assert_eq!(array[3], Mpz::from(1u64 << 40));
+        for z in &mut array {
+            unsafe { gmp::mpz_neg(z.as_raw_mut(), z.as_raw()) };
+        }
+        assert_eq!(array[9], Mpz::from(-61));
+        assert_eq!((&array).into_iter().count(), 10);
+        assert_eq!(MpzArray::from(values.clone()).as_slice(), &values[..]);
+        assert_eq!(MpzArray::from_slice(&values).as_slice(), &values[..]);
+        assert!(MpzArray::new(4).iter().all(|z| z.signum() == 0));
+    }
+
+    #[test]
+    fn clone_and_drop() {
+        let mut rng = Rng::new(8);
+        let mut array = MpzArray::new(50);
+        array.urandomb(&mut rng, 300);
+        assert!(array.iter().all(|z| z.significant_bits() <= 300));
+        let clone = array.clone();
+        drop(array);
+        assert_eq!(clone.len(), 50);
+        let copy: MpzArray = clone.iter().cloned().collect();
+        drop(clone);
+        assert_eq!(copy.len(), 50);
+    }
+
+    #[test]
+    fn as_bases_and_exponents() {
+        let mut rng = Rng::new(9);
+        let modulus = rng.odd_modulus(200);
+        let mut bases = MpzArray::new(12);
+        bases.urandomb(&mut rng, 199);
+        let mut exponents = MpzArray::new(12);
+        exponents.urandomb(&mut rng, 100);
+        let expected = product(&bases, &exponents, &modulus);
+        let mut rop = Mpz::new();
+        spowm(&mut rop, &bases, &exponents, &modulus).unwrap();
+        assert_eq!(rop, expected);
+        let table = SpowmTable::new(&modulus, &bases, 3).unwrap();
+        assert_eq!(table.pow(&exponents).unwrap(), expected);
+    }
+}
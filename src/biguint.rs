@@ -0,0 +1,297 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Integration with [num-bigint](https://crates.io/crates/num-bigint), enabled
+//! by the `num-bigint` feature.
+//!
+//! [`BigUint`] is not layout-compatible with `mpz_t`, so the values are copied
+//! limb by limb into GMP integers. A [`Workspace`] keeps these integers between
+//! calls, so that a batch of operations only allocates when a vector or an
+//! integer is larger than any seen before.
+
+use crate::error::Result;
+use crate::mpz::{AsMpz, Mpz};
+use crate::prime::{self, RandSource};
+use crate::{FpowmTable, SpowmTable};
+use gmp_mpfr_sys::gmp::{self, limb_t};
+use num_bigint::BigUint;
+
+/// Sets `rop` to the value of `value`, reusing the limbs of `rop`.
+pub fn set_biguint(rop: &mut Mpz, value: &BigUint) {
+    unsafe {
+        if gmp::LIMB_BITS == 64 {
+            let n = value.iter_u64_digits().len();
+            let limbs = gmp::mpz_limbs_write(rop.as_raw_mut(), n.max(1) as _);
+            for (i, d) in value.iter_u64_digits().enumerate() {
+                *limbs.add(i) = d as limb_t;
+            }
+            gmp::mpz_limbs_finish(rop.as_raw_mut(), n as _);
+        } else {
+            let n = value.iter_u32_digits().len();
+            let limbs = gmp::mpz_limbs_write(rop.as_raw_mut(), n.max(1) as _);
+            for (i, d) in value.iter_u32_digits().enumerate() {
+                *limbs.add(i) = d as limb_t;
+            }
+            gmp::mpz_limbs_finish(rop.as_raw_mut(), n as _);
+        }
+    }
+}
+
+/// Converts the absolute value of `op` into a [`BigUint`].
+pub fn to_biguint<T: AsMpz>(op: &T) -> BigUint {
+    unsafe {
+        let n = gmp::mpz_size(op.as_raw());
+        let limbs = gmp::mpz_limbs_read(op.as_raw());
+        let mut digits = Vec::with_capacity(n * gmp::LIMB_BITS as usize / 32);
+        for i in 0..n {
+            let limb = *limbs.add(i) as u64;
+            digits.push(limb as u32);
+            if gmp::LIMB_BITS == 64 {
+                digits.push((limb >> 32) as u32);
+            }
+        }
+        BigUint::new(digits)
+    }
+}
+
+impl From<&BigUint> for Mpz {
+    fn from(value: &BigUint) -> Self {
+        let mut rop = Mpz::new();
+        set_biguint(&mut rop, value);
+        rop
+    }
+}
+
+impl From<&Mpz> for BigUint {
+    fn from(value: &Mpz) -> Self {
+        to_biguint(value)
+    }
+}
+
+/// Fills the first `values.len()` integers of `buf` and returns them.
+fn load<'a>(buf: &'a mut Vec<Mpz>, values: &[BigUint]) -> &'a [Mpz] {
+    if buf.len() < values.len() {
+        buf.resize_with(values.len(), Mpz::new);
+    }
+    for (z, v) in buf.iter_mut().zip(values) {
+        set_biguint(z, v);
+    }
+    &buf[..values.len()]
+}
+
+/// Conversion buffers for running the GMPMEE operations on [`BigUint`].
+///
+/// The buffers grow to the largest batch seen and are reused by later calls.
+#[derive(Debug, Default)]
+pub struct Workspace {
+    bases: Vec<Mpz>,
+    exponents: Vec<Mpz>,
+    scalar: Mpz,
+    modulus: Mpz,
+    rop: Mpz,
+}
+
+impl Workspace {
+    /// Creates a workspace with empty buffers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Computes the product of the `b_i^e_i` modulo `modulus`, see [`crate::spowm()`].
+    pub fn spowm(
+        &mut self,
+        bases: &[BigUint],
+        exponents: &[BigUint],
+        modulus: &BigUint,
+    ) -> Result<BigUint> {
+        set_biguint(&mut self.modulus, modulus);
+        let bases = load(&mut self.bases, bases);
+        let exponents = load(&mut self.exponents, exponents);
+        crate::spowm(&mut self.rop, bases, exponents, &self.modulus)?;
+        Ok(to_biguint(&self.rop))
+    }
+
+    /// Builds a table of precomputed products of the bases, see [`SpowmTable::new`].
+    pub fn spowm_table_new(
+        &mut self,
+        modulus: &BigUint,
+        bases: &[BigUint],
+        block_width: usize,
+    ) -> Result<SpowmTable> {
+        set_biguint(&mut self.modulus, modulus);
+        SpowmTable::new(&self.modulus, load(&mut self.bases, bases), block_width)
+    }
+
+    /// Computes the product of the `b_i^e_i` with a precomputed table, see [`SpowmTable::pow`].
+    pub fn spowm_table(&mut self, table: &SpowmTable, exponents: &[BigUint]) -> Result<BigUint> {
+        table.pow_into(&mut self.rop, load(&mut self.exponents, exponents))?;
+        Ok(to_biguint(&self.rop))
+    }
+
+    /// Builds a fixed base exponentiation table, see [`FpowmTable::new`].
+    pub fn fpowm_table_new(
+        &mut self,
+        base: &BigUint,
+        modulus: &BigUint,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<FpowmTable> {
+        set_biguint(&mut self.scalar, base);
+        set_biguint(&mut self.modulus, modulus);
+        FpowmTable::new(&self.scalar, &self.modulus, block_width, exponent_bitlen)
+    }
+
+    /// Computes a fixed base exponentiation, see [`FpowmTable::pow`].
+    pub fn fpowm(&mut self, table: &FpowmTable, exponent: &BigUint) -> Result<BigUint> {
+        set_biguint(&mut self.scalar, exponent);
+        table.pow_into(&mut self.rop, &self.scalar)?;
+        Ok(to_biguint(&self.rop))
+    }
+
+    /// Computes a fixed base exponentiation for each exponent, see
+    /// [`FpowmTable::pow_many`].
+    pub fn fpowm_many(
+        &mut self,
+        table: &FpowmTable,
+        exponents: &[BigUint],
+    ) -> Result<Vec<BigUint>> {
+        let powers = table.pow_many(load(&mut self.exponents, exponents))?;
+        Ok(powers.iter().map(to_biguint).collect())
+    }
+
+    /// Returns `false` if `n` is composite, see [`crate::millerrabin`].
+    pub fn is_probably_prime<R: RandSource>(
+        &mut self,
+        n: &BigUint,
+        reps: u32,
+        rand: &mut R,
+    ) -> bool {
+        set_biguint(&mut self.scalar, n);
+        prime::millerrabin(&self.scalar, reps, rand)
+    }
+
+    /// Returns `false` if `n` is not a safe prime, see [`crate::millerrabin_safe`].
+    pub fn is_probably_safe_prime<R: RandSource>(
+        &mut self,
+        n: &BigUint,
+        reps: u32,
+        rand: &mut R,
+    ) -> bool {
+        set_biguint(&mut self.scalar, n);
+        prime::millerrabin_safe(&self.scalar, reps, rand)
+    }
+
+    /// Returns the smallest prime larger than `n`, see [`crate::millerrabin_next`].
+    pub fn next_prime<R: RandSource>(&mut self, n: &BigUint, reps: u32, rand: &mut R) -> BigUint {
+        set_biguint(&mut self.scalar, n);
+        prime::millerrabin_next_into(&mut self.rop, &self.scalar, reps, rand);
+        to_biguint(&self.rop)
+    }
+
+    /// Returns the smallest safe prime larger than `n`, see [`crate::millerrabin_safe_next`].
+    pub fn next_safe_prime<R: RandSource>(
+        &mut self,
+        n: &BigUint,
+        reps: u32,
+        rand: &mut R,
+    ) -> BigUint {
+        set_biguint(&mut self.scalar, n);
+        prime::millerrabin_safe_next_into(&mut self.rop, &self.scalar, reps, rand);
+        to_biguint(&self.rop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Rng;
+
+    fn values() -> Vec<BigUint> {
+        vec![
+            BigUint::ZERO,
+            BigUint::from(1u32),
+            BigUint::from(u32::MAX),
+            BigUint::from(u64::MAX),
+            BigUint::from(u64::MAX) + 1u32,
+            (BigUint::from(1u32) << 1000) - 1u32,
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for value in values() {
+            let mpz = Mpz::from(&value);
+            assert_eq!(mpz.to_string(), value.to_string());
+            assert_eq!(BigUint::from(&mpz), value);
+        }
+    }
+
+    #[test]
+    fn reuse_larger_rop() {
+        let mut rop = Mpz::from(&((BigUint::from(1u32) << 2000) - 1u32));
+        for value in values().into_iter().rev() {
+            set_biguint(&mut rop, &value);
+            assert_eq!(to_biguint(&rop), value);
+            assert_eq!(rop.to_string(), value.to_string());
+        }
+    }
+
+    #[test]
+    fn to_biguint_drops_the_sign() {
+        assert_eq!(to_biguint(&Mpz::from(-12345i32)), BigUint::from(12345u32));
+    }
+
+    #[test]
+    fn workspace_reuses_buffers() {
+        let modulus = (BigUint::from(1u32) << 127) - 1u32;
+        let mut workspace = Workspace::new();
+        for len in [8usize, 3, 0, 5] {
+            let bases: Vec<_> = (0..len).map(|i| BigUint::from(i as u32 + 2)).collect();
+            let exponents: Vec<_> = (0..len)
+                .map(|i| BigUint::from(1u32) << (10 * i) | BigUint::from(7u32))
+                .collect();
+            let expected = bases
+                .iter()
+                .zip(&exponents)
+                .fold(BigUint::from(1u32), |acc, (b, e)| {
+                    acc * b.modpow(e, &modulus) % &modulus
+                });
+            assert_eq!(
+                workspace.spowm(&bases, &exponents, &modulus).unwrap(),
+                expected
+            );
+            let table = workspace.spowm_table_new(&modulus, &bases, 2).unwrap();
+            assert_eq!(workspace.spowm_table(&table, &exponents).unwrap(), expected);
+        }
+        let base = BigUint::from(3u32);
+        let table = workspace.fpowm_table_new(&base, &modulus, 4, 64).unwrap();
+        let exponents = [BigUint::ZERO, BigUint::from(u64::MAX), BigUint::from(5u32)];
+        let expected: Vec<_> = exponents.iter().map(|e| base.modpow(e, &modulus)).collect();
+        assert_eq!(workspace.fpowm(&table, &exponents[1]).unwrap(), expected[1]);
+        assert_eq!(workspace.fpowm_many(&table, &exponents).unwrap(), expected);
+        let mut rng = Rng::new(5);
+        assert!(workspace.is_probably_prime(&modulus, 20, &mut rng));
+        assert!(workspace.is_probably_safe_prime(&BigUint::from(107u32), 20, &mut rng));
+        assert_eq!(
+            workspace.next_prime(&BigUint::from(89u32), 20, &mut rng),
+            BigUint::from(97u32)
+        );
+        assert_eq!(
+            workspace.next_safe_prime(&BigUint::from(11u32), 20, &mut rng),
+            BigUint::from(23u32)
+        );
+    }
+}
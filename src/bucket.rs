@@ -0,0 +1,214 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::cost::Cost;
+use crate::error::Result;
+use crate::mpz::{AsMpz, Mpz};
+use crate::signed::{has_negative, spowm_signed};
+use crate::spowm::{
+    BlockBatch, MAX_BLOCK_WIDTH, check_block_width, check_len, check_modulus, max_bitlen,
+};
+use gmp_mpfr_sys::gmp::{self, limb_t};
+
+const LIMB_BITS: usize = gmp::LIMB_BITS as usize;
+
+/// Number of bases from which [`spowm`](crate::spowm()) considers
+/// [`spowm_bucket`] instead of the block method.
+///
+/// From it on, the method with the fewest modular multiplications is chosen,
+/// comparing [`Cost::bucket`] with [`Cost::spowm`] for the block width of the
+/// block method. The aggregation of the buckets takes `2^(window + 1)`
+/// multiplications per window, and the windows are as many as the bits over
+/// the window. The threshold is conservative: with 256-bit exponents over a
+/// 3072-bit modulus, the bucket method is already faster at a few hundred
+/// bases, see `cargo run --release --example bucket`, but below the threshold
+/// `spowm` keeps the block method; with 3072-bit exponents, the block method
+/// still takes fewer multiplications at a thousand bases.
+///
+/// Only the Rust [`spowm`](crate::spowm()) switches methods:
+/// [`gmpmee_spowm`](crate::gmpmee_spowm) always uses the block method.
+pub const BUCKET_THRESHOLD: usize = 1 << 10;
+
+/// Returns `true` if [`spowm_bucket`] takes fewer modular multiplications than
+/// the block method with `block_width`, for `len` exponents of at most
+/// `exponent_bits` bits. The memory, the only cost depending on the modulus,
+/// is not compared.
+pub(crate) fn bucket_is_cheaper(len: usize, exponent_bits: usize, block_width: usize) -> bool {
+    let window = bucket_window(len, exponent_bits);
+    let (Ok(bucket), Ok(params)) = (
+        Cost::bucket(0, exponent_bits, len, window),
+        BlockBatch::new(block_width, len.max(1)),
+    ) else {
+        return false;
+    };
+    bucket.total(1) < Cost::spowm(0, exponent_bits, len, params).total(1)
+}
+
+/// Number of multiplications, squarings aside, of [`spowm_bucket`] with
+/// `window`, see [`Cost::bucket`](crate::Cost::bucket).
+pub(crate) fn bucket_multiplications(len: usize, exponent_bits: usize, window: usize) -> usize {
+    exponent_bits.div_ceil(window) * (len + (2 << window))
+}
+
+/// Window minimizing the number of modular multiplications of [`spowm_bucket`]
+/// for `len` exponents of at most `exponent_bits` bits.
+///
+/// The window lies in `1..=MAX_BLOCK_WIDTH`. Since the buckets cost
+/// `2^(window + 1)` multiplications per window, there are fewer buckets than
+/// bases as soon as `len` is large.
+pub fn bucket_window(len: usize, exponent_bits: usize) -> usize {
+    (1..=MAX_BLOCK_WIDTH)
+        .min_by_key(|&window| bucket_multiplications(len, exponent_bits, window))
+        .unwrap_or(1)
+}
+
+/// Returns the `width` bits of `limbs` from bit `start`, with zeros above the
+/// last limb. `width` must be smaller than `LIMB_BITS`.
+fn digit(limbs: &[limb_t], start: usize, width: usize) -> usize {
+    let (index, shift) = (start / LIMB_BITS, start % LIMB_BITS);
+    let mut bits = limbs.get(index).map_or(0, |&limb| limb >> shift);
+    if shift + width > LIMB_BITS {
+        bits |= limbs
+            .get(index + 1)
+            .map_or(0, |&limb| limb << (LIMB_BITS - shift));
+    }
+    (bits & ((1 << width) - 1)) as usize
+}
+
+/// Sets `rop` to `op`, if `rop` is the empty product, or to `rop * op`,
+/// modulo `modulus`.
+fn mul_into<T: AsMpz, M: AsMpz>(rop: &mut Option<Mpz>, op: &T, modulus: &M) {
+    let rop = rop.get_or_insert_with(|| Mpz::from(1u32));
+    unsafe {
+        gmp::mpz_mul(rop.as_raw_mut(), rop.as_raw(), op.as_raw());
+        gmp::mpz_mod(rop.as_raw_mut(), rop.as_raw(), modulus.as_raw());
+    }
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` with the bucket
+/// method of Pippenger, writing the result into `rop`.
+///
+/// The exponents are read in windows of `window` bits, from the most
+/// significant one. For each window, every base is multiplied into the bucket
+/// of its digit, and the buckets are combined into the product of
+/// `bucket_d^d` with two running products, before `window` squarings of the
+/// result. Each window costs one multiplication per base, instead of one per
+/// block of bases and per bit with [`gmpmee_spowm`](crate::gmpmee_spowm), and
+/// the memory holds `2^window` buckets whatever the number of bases.
+///
+/// The window must lie in `1..=MAX_BLOCK_WIDTH`, see [`bucket_window`]. The
+/// other parameters are checked as in [`spowm`](crate::spowm()).
+pub fn spowm_bucket<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+    window: usize,
+) -> Result<()> {
+    check_block_width(window)?;
+    if has_negative(exponents) {
+        return spowm_signed(rop, bases, exponents, modulus, |rop, bases, exponents| {
+            spowm_bucket(rop, bases, exponents, modulus, window)
+        });
+    }
+    check_modulus(modulus)?;
+    check_len(bases.len(), exponents.len())?;
+    let limbs: Vec<&[limb_t]> = exponents
+        .iter()
+        .map(|e| unsafe {
+            let size = gmp::mpz_size(e.as_raw());
+            match size {
+                0 => &[][..],
+                _ => std::slice::from_raw_parts(gmp::mpz_limbs_read(e.as_raw()), size),
+            }
+        })
+        .collect();
+    let bitlen = max_bitlen(exponents);
+    let mut result: Option<Mpz> = None;
+    let mut buckets: Vec<Option<Mpz>> = vec![None; 1 << window];
+    for start in (0..bitlen.div_ceil(window)).rev().map(|k| k * window) {
+        if let Some(result) = &mut result {
+            for _ in 0..window {
+                unsafe {
+                    gmp::mpz_mul(result.as_raw_mut(), result.as_raw(), result.as_raw());
+                    gmp::mpz_mod(result.as_raw_mut(), result.as_raw(), modulus.as_raw());
+                }
+            }
+        }
+        for (base, limbs) in bases.iter().zip(&limbs) {
+            match digit(limbs, start, window) {
+                0 => {}
+                d => mul_into(&mut buckets[d], base, modulus),
+            }
+        }
+        // The running product of the buckets from d up is multiplied into the
+        // result once for each d, so that bucket d is raised to the power d.
+        let mut running: Option<Mpz> = None;
+        for bucket in buckets.iter_mut().skip(1).rev() {
+            if let Some(bucket) = bucket.take() {
+                mul_into(&mut running, &bucket, modulus);
+            }
+            if let Some(running) = &running {
+                mul_into(&mut result, running, modulus);
+            }
+        }
+    }
+    match result {
+        Some(result) => unsafe { gmp::mpz_set(rop.as_raw_mut(), result.as_raw()) },
+        None => unsafe { gmp::mpz_set_ui(rop.as_raw_mut(), 1) },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Rng;
+    use crate::tune::spowm_block_width;
+    use crate::{spowm, spowm_naive};
+
+    #[test]
+    fn bucket_is_cheaper_for_short_exponents() {
+        let modulus = Rng::new(22).odd_modulus(3072);
+        let cheaper = |bits| bucket_is_cheaper(1024, bits, spowm_block_width(&modulus, bits));
+        assert!(cheaper(256));
+        assert!(!cheaper(3072));
+    }
+
+    #[test]
+    fn spowm_matches_naive_around_threshold() {
+        let mut rng = Rng::new(23);
+        let modulus = rng.odd_modulus(256);
+        let len = BUCKET_THRESHOLD;
+        let bases: Vec<_> = (0..len).map(|_| rng.below(&modulus)).collect();
+        // The bucket method is chosen for the short exponents of the full
+        // vector, and the block method for the long ones and the shorter vector.
+        for (bits, bucket) in [(64, true), (1024, false)] {
+            let exponents: Vec<_> = (0..len).map(|_| rng.bits(bits)).collect();
+            let block_width = spowm_block_width(&modulus, max_bitlen(&exponents));
+            assert_eq!(
+                bucket_is_cheaper(len, max_bitlen(&exponents), block_width),
+                bucket
+            );
+            for len in [len - 1, len] {
+                let (mut rop, mut expected) = (Mpz::new(), Mpz::new());
+                spowm(&mut rop, &bases[..len], &exponents[..len], &modulus).unwrap();
+                spowm_naive(&mut expected, &bases[..len], &exponents[..len], &modulus).unwrap();
+                assert_eq!(rop, expected);
+            }
+        }
+    }
+}
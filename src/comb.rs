@@ -0,0 +1,441 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::error::{Error, Result};
+use crate::fpowm::FpowmTable;
+use crate::mpz::{AsMpz, Mpz};
+use crate::signed::{fpowm_signed, is_negative};
+use crate::spowm::{SpowmTable, check_block_width, check_modulus};
+use gmp_mpfr_sys::gmp;
+
+/// Table for fixed base exponentiation with the Lim-Lee comb method.
+///
+/// The exponent of `exponent_bitlen` bits is split in `teeth` rows of `a`
+/// bits, and each row in `combs` columns of `spacing` bits, with
+/// `combs = ceil(exponent_bitlen / (teeth * spacing))` and `a = combs * spacing`.
+/// Each comb reads one bit of every row at once, in a subtable of
+/// `2^teeth` entries, and consecutive combs are `spacing` bits apart.
+///
+/// An exponentiation costs `spacing` squarings and `combs * spacing`
+/// multiplications, for a table of `combs * 2^teeth` entries: the width of
+/// the subtables and the number of squarings are chosen separately, whereas
+/// a [`FpowmTable`] of block width `w` is the single comb with `w` teeth and
+/// `ceil(exponent_bitlen / w)` squarings.
+///
+/// The table is a [`SpowmTable`] of block width `teeth` over the bases
+/// `b^(2^(i * a + j * spacing))`, one block per comb, evaluated with the
+/// `spacing`-bit chunks of the exponent. Longer exponents are still accepted,
+/// their extra bits are added to the last chunk, and the results equal those
+/// of [`FpowmTable::pow`].
+#[derive(Debug, Clone)]
+pub struct CombTable {
+    table: SpowmTable,
+    teeth: usize,
+    spacing: usize,
+    combs: usize,
+}
+
+impl CombTable {
+    /// Computes the bases and the subtables of the combs.
+    ///
+    /// The modulus must be greater than one, the number of teeth must lie in
+    /// `1..=MAX_BLOCK_WIDTH`, and the spacing and the exponent bit length must
+    /// not be zero.
+    pub fn new<B: AsMpz, M: AsMpz>(
+        base: &B,
+        modulus: &M,
+        teeth: usize,
+        spacing: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        check_modulus(modulus)?;
+        check_block_width(teeth)?;
+        if spacing == 0 {
+            return Err(Error::InvalidSpacing(spacing));
+        }
+        if exponent_bitlen == 0 {
+            return Err(Error::InvalidExponentBitlen(exponent_bitlen));
+        }
+        let combs = exponent_bitlen.div_ceil(teeth * spacing);
+        // The bases b^(2^(k * spacing)) by one chain of squarings, where
+        // k = i * combs + j for the tooth i of the comb j, stored comb by comb.
+        let mut powers = vec![Mpz::new(); teeth * combs];
+        let mut power = Mpz::new();
+        unsafe { gmp::mpz_mod(power.as_raw_mut(), base.as_raw(), modulus.as_raw()) };
+        for k in 0..teeth * combs {
+            let (i, j) = (k / combs, k % combs);
+            if k > 0 {
+                for _ in 0..spacing {
+                    unsafe {
+                        gmp::mpz_mul(power.as_raw_mut(), power.as_raw(), power.as_raw());
+                        gmp::mpz_mod(power.as_raw_mut(), power.as_raw(), modulus.as_raw());
+                    }
+                }
+            }
+            powers[j * teeth + i] = power.clone();
+        }
+        Ok(Self {
+            table: SpowmTable::new(modulus, &powers, teeth)?,
+            teeth,
+            spacing,
+            combs,
+        })
+    }
+
+    /// Number of bits read by each comb.
+    pub fn teeth(&self) -> usize {
+        self.teeth
+    }
+
+    /// Distance in bits between consecutive combs, the number of squarings.
+    pub fn spacing(&self) -> usize {
+        self.spacing
+    }
+
+    /// Number of combs, one subtable each.
+    pub fn combs(&self) -> usize {
+        self.combs
+    }
+
+    /// Number of exponent bits covered by the combs, `teeth * combs * spacing`.
+    pub fn exponent_bitlen(&self) -> usize {
+        self.teeth * self.combs * self.spacing
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        self.table.modulus()
+    }
+
+    /// Splits `exponent` in the chunks of the bases of the table.
+    fn chunks<E: AsMpz>(&self, exponent: &E) -> Vec<Mpz> {
+        let last = self.exponent_bitlen() - self.spacing;
+        let mut chunks = vec![Mpz::new(); self.teeth * self.combs];
+        for k in 0..self.teeth * self.combs {
+            let (i, j) = (k / self.combs, k % self.combs);
+            let chunk = &mut chunks[j * self.teeth + i];
+            let shift = k * self.spacing;
+            unsafe {
+                gmp::mpz_fdiv_q_2exp(chunk.as_raw_mut(), exponent.as_raw(), shift as _);
+                if shift < last {
+                    gmp::mpz_fdiv_r_2exp(chunk.as_raw_mut(), chunk.as_raw(), self.spacing as _);
+                }
+            }
+        }
+        chunks
+    }
+
+    /// Computes the base to the power `exponent` modulo the modulus.
+    ///
+    /// A negative exponent inverts the power, or returns
+    /// [`Error::NotInvertible`](crate::Error::NotInvertible) if the base is not
+    /// invertible modulo the modulus.
+    pub fn pow<E: AsMpz>(&self, exponent: &E) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponent)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponent: &E) -> Result<()> {
+        if is_negative(exponent) {
+            return fpowm_signed(rop, exponent, self.modulus(), |rop, exponent| {
+                self.pow_into(rop, exponent)
+            });
+        }
+        self.table.pow_into(rop, &self.chunks(exponent))
+    }
+
+    /// Computes the base to the power of each exponent modulo the modulus.
+    ///
+    /// With the `rayon` feature, the exponents are processed in parallel on
+    /// the current rayon thread pool, all reading the same table. Negative
+    /// exponents are handled as in [`pow`](Self::pow).
+    pub fn pow_many<E: AsMpz + Sync>(&self, exponents: &[E]) -> Result<Vec<Mpz>> {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            exponents.par_iter().map(|e| self.pow(e)).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        exponents.iter().map(|e| self.pow(e)).collect()
+    }
+}
+
+/// Method of a [`FixedBaseTable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedBaseMethod {
+    /// [`FpowmTable`] with the given block width.
+    Fpowm {
+        /// Number of parts the exponent is split into.
+        block_width: usize,
+    },
+    /// [`CombTable`] with the given number of teeth and spacing.
+    Comb {
+        /// Number of bits read by each comb.
+        teeth: usize,
+        /// Distance in bits between consecutive combs.
+        spacing: usize,
+    },
+}
+
+/// Table for fixed base exponentiation with a method chosen at runtime.
+#[derive(Debug, Clone)]
+pub enum FixedBaseTable {
+    /// Table of [`FixedBaseMethod::Fpowm`].
+    Fpowm(FpowmTable),
+    /// Table of [`FixedBaseMethod::Comb`].
+    Comb(CombTable),
+}
+
+impl FixedBaseTable {
+    /// Builds the table of `method` for exponents of `exponent_bitlen` bits, see
+    /// [`FpowmTable::new`] and [`CombTable::new`].
+    pub fn new<B: AsMpz, M: AsMpz>(
+        base: &B,
+        modulus: &M,
+        method: FixedBaseMethod,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        match method {
+            FixedBaseMethod::Fpowm { block_width } => Ok(Self::Fpowm(FpowmTable::new(
+                base,
+                modulus,
+                block_width,
+                exponent_bitlen,
+            )?)),
+            FixedBaseMethod::Comb { teeth, spacing } => Ok(Self::Comb(CombTable::new(
+                base,
+                modulus,
+                teeth,
+                spacing,
+                exponent_bitlen,
+            )?)),
+        }
+    }
+
+    /// Method and parameters of the table.
+    pub fn method(&self) -> FixedBaseMethod {
+        match self {
+            Self::Fpowm(table) => FixedBaseMethod::Fpowm {
+                block_width: table.block_width(),
+            },
+            Self::Comb(table) => FixedBaseMethod::Comb {
+                teeth: table.teeth(),
+                spacing: table.spacing(),
+            },
+        }
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        match self {
+            Self::Fpowm(table) => table.modulus(),
+            Self::Comb(table) => table.modulus(),
+        }
+    }
+
+    /// Computes the base to the power `exponent` modulo the modulus.
+    ///
+    /// A negative exponent inverts the power, or returns
+    /// [`Error::NotInvertible`](crate::Error::NotInvertible) if the base is not
+    /// invertible modulo the modulus.
+    pub fn pow<E: AsMpz>(&self, exponent: &E) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponent)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponent: &E) -> Result<()> {
+        match self {
+            Self::Fpowm(table) => table.pow_into(rop, exponent),
+            Self::Comb(table) => table.pow_into(rop, exponent),
+        }
+    }
+
+    /// Computes the base to the power of each exponent modulo the modulus, see
+    /// [`FpowmTable::pow_many`].
+    pub fn pow_many<E: AsMpz + Sync>(&self, exponents: &[E]) -> Result<Vec<Mpz>> {
+        match self {
+            Self::Fpowm(table) => table.pow_many(exponents),
+            Self::Comb(table) => table.pow_many(exponents),
+        }
+    }
+}
+
+impl From<FpowmTable> for FixedBaseTable {
+    fn from(table: FpowmTable) -> Self {
+        Self::Fpowm(table)
+    }
+}
+
+impl From<CombTable> for FixedBaseTable {
+    fn from(table: CombTable) -> Self {
+        Self::Comb(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmpmee::gmpmee_fpowm;
+    use crate::testing::{Rng, neg, powm};
+
+    /// Computes with `gmpmee_fpowm` directly, for a table built by `FpowmTable`.
+    fn fpowm(table: &FpowmTable, exponent: &Mpz) -> Mpz {
+        let mut rop = Mpz::new();
+        unsafe { gmpmee_fpowm(rop.as_raw_mut(), table.as_raw(), exponent.as_raw()) };
+        rop
+    }
+
+    #[test]
+    fn comb_matches_fpowm() {
+        let mut rng = Rng::new(21);
+        // A single comb, several combs, and a last row cut short.
+        for (teeth, spacing, exponent_bitlen) in [
+            (1, 1, 10),
+            (2, 3, 100),
+            (4, 8, 256),
+            (8, 32, 256),
+            (3, 5, 7),
+            (5, 4, 300),
+        ] {
+            let modulus = rng.odd_modulus(256);
+            let base = rng.below(&modulus);
+            let comb = CombTable::new(&base, &modulus, teeth, spacing, exponent_bitlen).unwrap();
+            let combs = exponent_bitlen.div_ceil(teeth * spacing);
+            assert_eq!(comb.teeth(), teeth);
+            assert_eq!(comb.spacing(), spacing);
+            assert_eq!(comb.combs(), combs);
+            assert_eq!(comb.exponent_bitlen(), teeth * combs * spacing);
+            assert_eq!(comb.modulus(), &modulus);
+            let table = FpowmTable::new(&base, &modulus, teeth, exponent_bitlen).unwrap();
+            // Exponents longer than the combs cover add their extra bits to
+            // the last chunk.
+            let covered = comb.exponent_bitlen();
+            let exponents: Vec<_> = [0, 1, exponent_bitlen, covered, covered + 1, covered + 200]
+                .into_iter()
+                .map(|bits| rng.bits(bits))
+                .collect();
+            for exponent in &exponents {
+                let expected = table.pow(exponent).unwrap();
+                assert_eq!(fpowm(&table, exponent), expected);
+                assert_eq!(comb.pow(exponent).unwrap(), expected, "{teeth} {spacing}");
+            }
+            let expected: Vec<_> = exponents.iter().map(|e| table.pow(e).unwrap()).collect();
+            assert_eq!(comb.pow_many(&exponents).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn even_modulus_and_zero_base() {
+        let mut rng = Rng::new(22);
+        let mut modulus = rng.exact_bits(200);
+        unsafe { gmp::mpz_clrbit(modulus.as_raw_mut(), 0) };
+        for base in [Mpz::new(), rng.below(&modulus)] {
+            let comb = CombTable::new(&base, &modulus, 3, 7, 100).unwrap();
+            for bits in [0, 50, 100, 400] {
+                let exponent = rng.bits(bits);
+                assert_eq!(
+                    comb.pow(&exponent).unwrap(),
+                    powm(&base, &exponent, &modulus)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn negative_exponents() {
+        let mut rng = Rng::new(23);
+        let modulus = rng.prime(128);
+        let base = rng.below(&modulus);
+        let comb = CombTable::new(&base, &modulus, 4, 6, 128).unwrap();
+        let exponent = neg(&rng.bits(128));
+        assert_eq!(
+            comb.pow(&exponent).unwrap(),
+            powm(&base, &exponent, &modulus)
+        );
+        let zero = CombTable::new(&Mpz::new(), &modulus, 4, 6, 128).unwrap();
+        assert_eq!(zero.pow(&exponent), Err(Error::NotInvertible));
+    }
+
+    #[test]
+    fn fixed_base_tables() {
+        let mut rng = Rng::new(24);
+        let modulus = rng.odd_modulus(256);
+        let base = rng.below(&modulus);
+        let table = FpowmTable::new(&base, &modulus, 4, 160).unwrap();
+        let exponents: Vec<_> = [0, 1, 160, 161, 500]
+            .into_iter()
+            .map(|bits| rng.bits(bits))
+            .collect();
+        let expected: Vec<_> = exponents.iter().map(|e| fpowm(&table, e)).collect();
+        for method in [
+            FixedBaseMethod::Fpowm { block_width: 1 },
+            FixedBaseMethod::Fpowm { block_width: 6 },
+            FixedBaseMethod::Comb {
+                teeth: 1,
+                spacing: 16,
+            },
+            FixedBaseMethod::Comb {
+                teeth: 5,
+                spacing: 4,
+            },
+        ] {
+            let fixed = FixedBaseTable::new(&base, &modulus, method, 160).unwrap();
+            assert_eq!(fixed.method(), method);
+            assert_eq!(fixed.modulus(), &modulus);
+            for (exponent, expected) in exponents.iter().zip(&expected) {
+                assert_eq!(fixed.pow(exponent).unwrap(), *expected, "{method:?}");
+            }
+            assert_eq!(fixed.pow_many(&exponents).unwrap(), expected);
+        }
+        let fixed = FixedBaseTable::from(table);
+        assert_eq!(fixed.method(), FixedBaseMethod::Fpowm { block_width: 4 });
+        assert_eq!(fixed.pow(&exponents[4]).unwrap(), expected[4]);
+        let comb = CombTable::new(&base, &modulus, 2, 10, 160).unwrap();
+        let fixed = FixedBaseTable::from(comb);
+        assert_eq!(
+            fixed.method(),
+            FixedBaseMethod::Comb {
+                teeth: 2,
+                spacing: 10
+            }
+        );
+        assert_eq!(fixed.pow(&exponents[3]).unwrap(), expected[3]);
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let (base, modulus) = (Mpz::from(3u32), Mpz::from(101u32));
+        let comb = |teeth, spacing, bitlen| {
+            CombTable::new(&base, &modulus, teeth, spacing, bitlen).map(|_| ())
+        };
+        assert_eq!(comb(0, 4, 64), Err(Error::InvalidBlockWidth(0)));
+        assert_eq!(comb(4, 0, 64), Err(Error::InvalidSpacing(0)));
+        assert_eq!(comb(4, 4, 0), Err(Error::InvalidExponentBitlen(0)));
+        assert_eq!(
+            CombTable::new(&base, &Mpz::from(1u32), 4, 4, 64).map(|_| ()),
+            Err(Error::InvalidModulus)
+        );
+        let method = FixedBaseMethod::Fpowm { block_width: 2 };
+        assert_eq!(
+            FixedBaseTable::new(&base, &Mpz::from(100u32), method, 64).map(|_| ()),
+            Err(Error::InvalidModulus)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::bucket::bucket_multiplications;
+use crate::error::{Error, Result};
+use crate::spowm::{BlockBatch, check_block_width};
+
+/// Number of multiplications, beyond the trivial ones, to fill a subtable of
+/// `block_width` bases: `2^w - w - 1`, as in `theoretical_block_width` in
+/// `spowm.c`.
+fn subtable_multiplications(block_width: usize) -> usize {
+    (1 << block_width) - block_width - 1
+}
+
+/// Predicted number of modular multiplications and table memory of an
+/// exponentiation.
+///
+/// The counts follow the C functions: every bit scanned costs one squaring and
+/// one multiplication per subtable, even when the multiplier is one. A
+/// squaring is counted as a multiplication in [`total`](Self::total).
+///
+/// The tables of the fixed base kinds are built once and reused by every
+/// exponentiation, while [`spowm`](Self::spowm), [`bucket`](Self::bucket) and
+/// [`powm`](Self::powm) build theirs in each call, see
+/// [`reusable`](Self::reusable).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cost {
+    /// Multiplications and squarings to build the tables.
+    pub precomputation: usize,
+    /// Squarings of each exponentiation.
+    pub squarings: usize,
+    /// Multiplications of each exponentiation.
+    pub multiplications: usize,
+    /// Estimated bytes of the tables, see [`BlockBatch::block_bytes`].
+    pub table_bytes: usize,
+    /// Whether the precomputation is done once for all the exponentiations,
+    /// rather than in each of them.
+    pub reusable: bool,
+}
+
+impl Cost {
+    /// Cost of a simultaneous exponentiation of `len` bases, with exponents of
+    /// at most `exponent_bits` bits, as computed by
+    /// [`spowm_block_batch`](crate::spowm_block_batch) with `params`.
+    ///
+    /// Each batch builds its table and scans the exponent bits on its own, and
+    /// its result is multiplied into the product. The memory is that of the
+    /// largest batch, the only one held at a time.
+    pub fn spowm(
+        modulus_bits: usize,
+        exponent_bits: usize,
+        len: usize,
+        params: BlockBatch,
+    ) -> Self {
+        let block_width = params.block_width();
+        let batch_len = params.batch_len();
+        let mut cost = Self::default();
+        for start in (0..len).step_by(batch_len) {
+            let batch = batch_len.min(len - start);
+            let width = block_width.min(batch);
+            let blocks = batch.div_ceil(width);
+            let last = batch - (blocks - 1) * width;
+            cost.precomputation +=
+                (blocks - 1) * subtable_multiplications(width) + subtable_multiplications(last);
+            cost.squarings += exponent_bits;
+            cost.multiplications += blocks * exponent_bits + 1;
+        }
+        if len > 0 {
+            let batch = batch_len.min(len);
+            cost.table_bytes = batch.div_ceil(block_width.min(batch))
+                * BlockBatch::block_bytes(modulus_bits, block_width.min(batch));
+        }
+        cost
+    }
+
+    /// Cost of a [`FpowmTable`](crate::FpowmTable) with the given parameters:
+    /// building the table once, and then each exponentiation with an exponent
+    /// of at most `exponent_bitlen` bits.
+    ///
+    /// The exponent is split in `block_width` parts of `stretch =
+    /// ceil(exponent_bitlen / block_width)` bits, as in `gmpmee_fpowm_init`.
+    /// The bases `b^(2^(i * stretch))` cost `stretch` squarings each, and each
+    /// exponentiation `stretch` squarings and multiplications. The block width
+    /// must lie in `1..=MAX_BLOCK_WIDTH` and the exponent bit length must not
+    /// be zero.
+    pub fn fpowm(modulus_bits: usize, block_width: usize, exponent_bitlen: usize) -> Result<Self> {
+        check_block_width(block_width)?;
+        if exponent_bitlen == 0 {
+            return Err(Error::InvalidExponentBitlen(exponent_bitlen));
+        }
+        let stretch = exponent_bitlen.div_ceil(block_width);
+        Ok(Self {
+            precomputation: (block_width - 1) * stretch + subtable_multiplications(block_width),
+            squarings: stretch,
+            multiplications: stretch,
+            table_bytes: BlockBatch::block_bytes(modulus_bits, block_width),
+            reusable: true,
+        })
+    }
+
+    /// Cost of a [`MultiFpowmTable`](crate::MultiFpowmTable) of `len` bases
+    /// with the given parameters: building the table once, and then each
+    /// product with exponents of at most `exponent_bitlen` bits.
+    ///
+    /// Each base costs as in [`fpowm`](Self::fpowm), except for the `stretch`
+    /// squarings of each product, which are shared by all the bases. The
+    /// parameters are checked as in [`fpowm`](Self::fpowm).
+    pub fn multi_fpowm(
+        modulus_bits: usize,
+        len: usize,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        let cost = Self::fpowm(modulus_bits, block_width, exponent_bitlen)?;
+        Ok(Self {
+            precomputation: len * cost.precomputation,
+            squarings: cost.squarings,
+            multiplications: len * cost.multiplications,
+            table_bytes: len * cost.table_bytes,
+            reusable: true,
+        })
+    }
+
+    /// Cost of a [`CombTable`](crate::CombTable) with the given parameters:
+    /// building the table once, and then each exponentiation with an exponent
+    /// of at most `exponent_bitlen` bits.
+    ///
+    /// With `combs = ceil(exponent_bitlen / (teeth * spacing))`, the bases cost
+    /// one chain of `(teeth * combs - 1) * spacing` squarings and the
+    /// subtables `combs * (2^teeth - teeth - 1)` multiplications, and each
+    /// exponentiation `spacing` squarings and `combs * spacing`
+    /// multiplications. The parameters are checked as in
+    /// [`CombTable::new`](crate::CombTable::new).
+    pub fn comb(
+        modulus_bits: usize,
+        teeth: usize,
+        spacing: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        check_block_width(teeth)?;
+        if spacing == 0 {
+            return Err(Error::InvalidSpacing(spacing));
+        }
+        if exponent_bitlen == 0 {
+            return Err(Error::InvalidExponentBitlen(exponent_bitlen));
+        }
+        let combs = exponent_bitlen.div_ceil(teeth * spacing);
+        Ok(Self {
+            precomputation: (teeth * combs - 1) * spacing + combs * subtable_multiplications(teeth),
+            squarings: spacing,
+            multiplications: combs * spacing,
+            table_bytes: combs * BlockBatch::block_bytes(modulus_bits, teeth),
+            reusable: true,
+        })
+    }
+
+    /// Cost of [`spowm_bucket`](crate::spowm_bucket) for `len` exponents of
+    /// at most `exponent_bits` bits with `window`.
+    ///
+    /// Each window multiplies every base into a bucket, combines the
+    /// `2^window - 1` buckets with two running products and squares the
+    /// result `window` times. There is no precomputation, and the memory is
+    /// that of the buckets. The window must lie in `1..=MAX_BLOCK_WIDTH`.
+    pub fn bucket(
+        modulus_bits: usize,
+        exponent_bits: usize,
+        len: usize,
+        window: usize,
+    ) -> Result<Self> {
+        check_block_width(window)?;
+        Ok(Self {
+            precomputation: 0,
+            squarings: exponent_bits,
+            multiplications: bucket_multiplications(len, exponent_bits, window),
+            table_bytes: BlockBatch::block_bytes(modulus_bits, window),
+            reusable: false,
+        })
+    }
+
+    /// Estimated cost of a plain `mpz_powm` with an exponent of
+    /// `exponent_bits` bits, for comparison.
+    ///
+    /// Assumes a sliding window of the width `k` minimizing
+    /// `2^(k - 1) + exponent_bits / (k + 1)`: the odd powers of the base are
+    /// precomputed, and each window costs one multiplication. GMP chooses its
+    /// window from its own thresholds, so this is an approximation.
+    pub fn powm(exponent_bits: usize) -> Self {
+        let (precomputation, multiplications) = (1..=10)
+            .map(|k| (1 << (k - 1), exponent_bits / (k + 1)))
+            .min_by_key(|&(p, m)| p + m)
+            .unwrap_or_default();
+        Self {
+            precomputation,
+            squarings: exponent_bits,
+            multiplications,
+            table_bytes: 0,
+            reusable: false,
+        }
+    }
+
+    /// Total number of modular multiplications, squarings included, for
+    /// `count` exponentiations.
+    ///
+    /// The precomputation is counted once if the tables are
+    /// [`reusable`](Self::reusable), and once per exponentiation otherwise.
+    pub fn total(&self, count: usize) -> usize {
+        let per_call = self.squarings + self.multiplications;
+        match self.reusable {
+            true => self.precomputation + count * per_call,
+            false => count * (self.precomputation + per_call),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn per_call(cost: &Cost) -> usize {
+        cost.squarings + cost.multiplications
+    }
+
+    /// Checks that the precomputation is counted once for reusable tables and
+    /// in each call otherwise.
+    fn check_total(cost: Cost, reusable: bool) {
+        assert_eq!(cost.reusable, reusable);
+        assert_eq!(cost.total(0), reusable as usize * cost.precomputation);
+        assert_eq!(cost.total(1), cost.precomputation + per_call(&cost));
+        let expected = match reusable {
+            true => cost.precomputation + 10 * per_call(&cost),
+            false => 10 * cost.total(1),
+        };
+        assert_eq!(cost.total(10), expected);
+    }
+
+    #[test]
+    fn total_spowm() {
+        let cost = Cost::spowm(2048, 256, 100, BlockBatch::new(4, 40).unwrap());
+        assert!(cost.precomputation > 0);
+        check_total(cost, false);
+    }
+
+    #[test]
+    fn total_powm() {
+        let cost = Cost::powm(256);
+        assert!(cost.precomputation > 0);
+        check_total(cost, false);
+    }
+
+    #[test]
+    fn total_bucket() {
+        let cost = Cost::bucket(2048, 256, 2000, 6).unwrap();
+        assert_eq!(cost.precomputation, 0);
+        check_total(cost, false);
+    }
+
+    #[test]
+    fn total_fpowm() {
+        check_total(Cost::fpowm(2048, 6, 256).unwrap(), true);
+    }
+
+    #[test]
+    fn total_multi_fpowm() {
+        check_total(Cost::multi_fpowm(2048, 3, 6, 256).unwrap(), true);
+    }
+
+    #[test]
+    fn total_comb() {
+        check_total(Cost::comb(2048, 6, 8, 256).unwrap(), true);
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Cryptographically secure random states, enabled by the `rand` feature.
+//!
+//! The GMP random states behind [`RandSource`] are not cryptographically
+//! secure. A [`CsprngState`] is a GMP random state whose bits are drawn from
+//! any [`CryptoRngCore`], so that the primality tests and prime searches pick
+//! their Miller-Rabin bases from a CSPRNG without any change to the C routines.
+//!
+//! The state also generates batches of secret integers, such as exponents below
+//! a group order or random subgroup elements, into an [`MpzArray`] or any slice
+//! of integers, ready to be passed to the exponentiation wrappers.
+
+use crate::array::MpzArray;
+use crate::error::{Error, Result};
+use crate::mpz::{AsMpz, Mpz};
+use crate::prime::RandSource;
+use crate::sec::SecFpowmTable;
+use gmp_mpfr_sys::gmp::{self, limb_t, mpz_t, randfnptr_t, randseed_t, randstate_ptr, randstate_t};
+use rand_core::CryptoRngCore;
+pub use rand_core::OsRng;
+use std::ffi::{c_ulong, c_void};
+use std::mem::{MaybeUninit, size_of};
+use std::ptr::NonNull;
+
+/// GMP random state drawing its bits from a [`CryptoRngCore`].
+///
+/// The generator is boxed and referenced from the seed of the GMP state, and
+/// the state dispatches to it with custom GMP callbacks. Seeding the state
+/// through GMP has no effect, and copying it with `gmp_randinit_set` aborts the
+/// process, since the generator cannot be duplicated.
+///
+/// The GMP routines cannot report errors, so a generator that fails or panics
+/// aborts the process.
+pub struct CsprngState<R: CryptoRngCore> {
+    raw: randstate_t,
+    rng: NonNull<R>,
+}
+
+// The state owns its generator, which is only reached through `&mut self`.
+unsafe impl<R: CryptoRngCore + Send> Send for CsprngState<R> {}
+
+impl<R: CryptoRngCore> CsprngState<R> {
+    const FUNCS: randfnptr_t = randfnptr_t {
+        seed: seed_callback,
+        get: get_callback::<R>,
+        clear: clear_callback,
+        iset: iset_callback,
+    };
+
+    /// Creates a random state drawing its bits from `rng`.
+    pub fn new(rng: R) -> Self {
+        let rng = NonNull::from(Box::leak(Box::new(rng)));
+        let raw = randstate_t {
+            seed: randseed_t {
+                alloc: MaybeUninit::uninit(),
+                size: MaybeUninit::uninit(),
+                d: rng.cast::<c_void>(),
+            },
+            alg: MaybeUninit::uninit(),
+            algdata: &Self::FUNCS,
+        };
+        Self { raw, rng }
+    }
+
+    /// Returns a reference to the generator.
+    pub fn rng(&self) -> &R {
+        unsafe { self.rng.as_ref() }
+    }
+
+    /// Returns a mutable reference to the generator.
+    pub fn rng_mut(&mut self) -> &mut R {
+        unsafe { self.rng.as_mut() }
+    }
+
+    /// Sets each integer of `rop` to a uniformly random integer in
+    /// `[0, bound)`.
+    ///
+    /// Candidates with the bit length of `bound - 1` are drawn and rejected
+    /// until one is below `bound`, so that each draw succeeds with probability
+    /// at least one half. The bound must be positive.
+    pub fn fill_below<T: AsMpz, B: AsMpz>(&mut self, rop: &mut [T], bound: &B) -> Result<()> {
+        if unsafe { gmp::mpz_sgn(bound.as_raw()) } <= 0 {
+            return Err(Error::InvalidBound);
+        }
+        let mut max = Mpz::new();
+        unsafe { gmp::mpz_sub_ui(max.as_raw_mut(), bound.as_raw(), 1) };
+        let bits = max.significant_bits();
+        for z in rop {
+            loop {
+                unsafe {
+                    gmp::mpz_urandomb(z.as_raw_mut(), self.as_raw_mut(), bits as c_ulong);
+                    if gmp::mpz_cmp(z.as_raw(), bound.as_raw()) < 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets each integer of `rop` to a uniformly random integer of exactly
+    /// `bits` bits, that is in `[2^(bits - 1), 2^bits)`.
+    ///
+    /// With zero bits, every integer is set to zero.
+    pub fn fill_bits<T: AsMpz>(&mut self, rop: &mut [T], bits: usize) {
+        for z in rop {
+            unsafe {
+                gmp::mpz_urandomb(
+                    z.as_raw_mut(),
+                    self.as_raw_mut(),
+                    bits.saturating_sub(1) as c_ulong,
+                );
+                if bits > 0 {
+                    gmp::mpz_setbit(z.as_raw_mut(), (bits - 1) as _);
+                }
+            }
+        }
+    }
+
+    /// Sets each integer of `rop` to a uniformly random element of the
+    /// subgroup generated by the base of `table`, of order `order`.
+    ///
+    /// Each element is the base raised to an exponent drawn as in
+    /// [`fill_below`](Self::fill_below). The exponents are secret, so the
+    /// powers are computed with the constant-time [`SecFpowmTable`], whose
+    /// [`exponent_bitlen`](SecFpowmTable::exponent_bitlen) must cover the
+    /// exponents below `order`, or [`Error::ExponentTooLong`] is returned.
+    pub fn fill_elements<T: AsMpz, Q: AsMpz>(
+        &mut self,
+        rop: &mut [T],
+        table: &SecFpowmTable,
+        order: &Q,
+    ) -> Result<()> {
+        if unsafe { gmp::mpz_sgn(order.as_raw()) } <= 0 {
+            return Err(Error::InvalidBound);
+        }
+        let mut max = Mpz::new();
+        unsafe { gmp::mpz_sub_ui(max.as_raw_mut(), order.as_raw(), 1) };
+        if max.significant_bits() > table.exponent_bitlen() {
+            return Err(Error::ExponentTooLong {
+                bits: max.significant_bits(),
+                max: table.exponent_bitlen(),
+            });
+        }
+        let mut exponent = [Mpz::new()];
+        for z in rop {
+            self.fill_below(&mut exponent, order)?;
+            table.pow_into(z, &exponent[0])?;
+        }
+        Ok(())
+    }
+
+    /// Returns an array of `len` uniformly random integers in `[0, bound)`, see
+    /// [`fill_below`](Self::fill_below).
+    pub fn array_below<B: AsMpz>(&mut self, len: usize, bound: &B) -> Result<MpzArray> {
+        let mut array = MpzArray::new(len);
+        self.fill_below(&mut array, bound)?;
+        Ok(array)
+    }
+
+    /// Returns an array of `len` uniformly random integers of exactly `bits`
+    /// bits, see [`fill_bits`](Self::fill_bits).
+    pub fn array_bits(&mut self, len: usize, bits: usize) -> MpzArray {
+        let mut array = MpzArray::new(len);
+        self.fill_bits(&mut array, bits);
+        array
+    }
+
+    /// Returns an array of `len` uniformly random subgroup elements, see
+    /// [`fill_elements`](Self::fill_elements).
+    pub fn array_elements<Q: AsMpz>(
+        &mut self,
+        len: usize,
+        table: &SecFpowmTable,
+        order: &Q,
+    ) -> Result<MpzArray> {
+        let mut array = MpzArray::new(len);
+        self.fill_elements(&mut array, table, order)?;
+        Ok(array)
+    }
+
+    /// Consumes the state and returns the generator.
+    pub fn into_rng(self) -> R {
+        let rng = unsafe { Box::from_raw(self.rng.as_ptr()) };
+        std::mem::forget(self);
+        *rng
+    }
+}
+
+impl CsprngState<OsRng> {
+    /// Creates a random state drawing its bits from the entropy source of the
+    /// operating system.
+    pub fn from_os() -> Self {
+        Self::new(OsRng)
+    }
+}
+
+unsafe impl<R: CryptoRngCore> RandSource for CsprngState<R> {
+    fn as_raw_mut(&mut self) -> randstate_ptr {
+        &mut self.raw
+    }
+}
+
+impl<R: CryptoRngCore> Drop for CsprngState<R> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.rng.as_ptr()) });
+    }
+}
+
+impl<R: CryptoRngCore> std::fmt::Debug for CsprngState<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CsprngState").finish_non_exhaustive()
+    }
+}
+
+unsafe extern "C" fn seed_callback(_: *mut randstate_t, _: *const mpz_t) {}
+
+unsafe extern "C" fn get_callback<R: CryptoRngCore>(
+    rstate: *mut randstate_t,
+    dest: *mut limb_t,
+    nbits: c_ulong,
+) {
+    let bits = nbits as usize;
+    let limb_bits = gmp::LIMB_BITS as usize;
+    let n = bits.div_ceil(limb_bits);
+    if n == 0 {
+        return;
+    }
+    unsafe {
+        let rng = (*rstate).seed.d.cast::<R>().as_mut();
+        let bytes = std::slice::from_raw_parts_mut(dest.cast::<u8>(), n * size_of::<limb_t>());
+        rng.fill_bytes(bytes);
+        let rem = bits % limb_bits;
+        if rem != 0 {
+            *dest.add(n - 1) &= (1 << rem) - 1;
+        }
+    }
+}
+
+// The generator is owned and freed by `CsprngState`, not by `gmp_randclear`.
+unsafe extern "C" fn clear_callback(_: *mut randstate_t) {}
+
+unsafe extern "C" fn iset_callback(_: *mut randstate_t, _: *const randstate_t) {
+    std::process::abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fpowm::FpowmTable;
+    use crate::prime::{millerrabin, millerrabin_next};
+    use rand_core::{CryptoRng, RngCore};
+
+    /// Seeded splitmix64 generator, not secure but reproducible.
+    struct SplitMix(u64);
+
+    impl RngCore for SplitMix {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for SplitMix {}
+
+    /// Generator of ones only, to see which bits the callback keeps.
+    struct Ones;
+
+    impl RngCore for Ones {
+        fn next_u32(&mut self) -> u32 {
+            u32::MAX
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            u64::MAX
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0xff)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for Ones {}
+
+    #[test]
+    fn get_callback_masks_top_limb() {
+        let limb_bits = gmp::LIMB_BITS as usize;
+        let mut state = CsprngState::new(Ones);
+        for bits in [
+            1,
+            7,
+            limb_bits - 1,
+            limb_bits,
+            limb_bits + 1,
+            3 * limb_bits + 5,
+        ] {
+            // One limb past the requested ones must be left untouched.
+            let n = bits.div_ceil(limb_bits);
+            let mut limbs: Vec<limb_t> = vec![0x5a; n + 1];
+            unsafe { get_callback::<Ones>(state.as_raw_mut(), limbs.as_mut_ptr(), bits as _) };
+            let rem = bits % limb_bits;
+            for (i, &limb) in limbs[..n].iter().enumerate() {
+                let expected = match i + 1 == n && rem != 0 {
+                    true => (1 << rem) - 1,
+                    false => limb_t::MAX,
+                };
+                assert_eq!(limb, expected, "bits {bits} limb {i}");
+            }
+            assert_eq!(limbs[n], 0x5a);
+            let mut z = Mpz::new();
+            unsafe { gmp::mpz_urandomb(z.as_raw_mut(), state.as_raw_mut(), bits as _) };
+            assert_eq!(z.significant_bits(), bits);
+        }
+    }
+
+    #[test]
+    fn seeded_state_is_deterministic() {
+        let draw = |seed| {
+            let mut state = CsprngState::new(SplitMix(seed));
+            let mut values = vec![Mpz::new(); 8];
+            state.fill_bits(&mut values[..4], 200);
+            let bound = Mpz::from(1_000_003u32);
+            state.fill_below(&mut values[4..], &bound).unwrap();
+            values
+        };
+        assert_eq!(draw(1), draw(1));
+        assert_ne!(draw(1), draw(2));
+        let state = CsprngState::new(SplitMix(3));
+        assert_eq!(state.into_rng().0, 3);
+    }
+
+    #[test]
+    fn state_drives_millerrabin() {
+        let mut state = CsprngState::new(SplitMix(4));
+        let mut prime = Mpz::new();
+        unsafe {
+            gmp::mpz_setbit(prime.as_raw_mut(), 127);
+            gmp::mpz_sub_ui(prime.as_raw_mut(), prime.as_raw(), 1);
+        }
+        assert!(millerrabin(&prime, 20, &mut state));
+        assert!(!millerrabin(&Mpz::from(561u32), 20, &mut state));
+        assert_eq!(
+            millerrabin_next(&Mpz::from(89u32), 20, &mut state),
+            Mpz::from(97u32)
+        );
+        let mut os = CsprngState::from_os();
+        assert!(millerrabin(&prime, 20, &mut os));
+    }
+
+    #[test]
+    fn fill_below_and_bits() {
+        let mut state = CsprngState::new(SplitMix(5));
+        let bound = Mpz::from(1000u32);
+        let values = state.array_below(300, &bound).unwrap();
+        assert!(values.iter().all(|z| z.signum() >= 0 && *z < bound));
+        assert!(
+            state
+                .array_below(3, &Mpz::from(1u32))
+                .unwrap()
+                .iter()
+                .all(|z| z.signum() == 0)
+        );
+        assert_eq!(state.array_below(3, &Mpz::new()), Err(Error::InvalidBound));
+        assert_eq!(
+            state.array_below(3, &Mpz::from(-2i32)),
+            Err(Error::InvalidBound)
+        );
+        for bits in [0, 1, 63, 64, 65, 300] {
+            let values = state.array_bits(20, bits);
+            assert!(values.iter().all(|z| z.significant_bits() == bits));
+        }
+    }
+
+    #[test]
+    fn fill_elements_matches_table() {
+        // 2579 = 2 * 1289 + 1, and 4 generates the subgroup of order 1289.
+        let modulus = Mpz::from(2579u32);
+        let order = Mpz::from(1289u32);
+        let base = Mpz::from(4u32);
+        let table = SecFpowmTable::new(&base, &modulus, 3, 11).unwrap();
+        let elements = CsprngState::new(SplitMix(6))
+            .array_elements(40, &table, &order)
+            .unwrap();
+        // The same generator draws the same exponents.
+        let exponents = CsprngState::new(SplitMix(6))
+            .array_below(40, &order)
+            .unwrap();
+        let fixed = FpowmTable::new(&base, &modulus, 3, 11).unwrap();
+        for (element, exponent) in elements.iter().zip(&exponents) {
+            assert_eq!(*element, fixed.pow(exponent).unwrap());
+        }
+        let short = SecFpowmTable::new(&base, &modulus, 2, 8).unwrap();
+        let mut state = CsprngState::new(SplitMix(7));
+        assert_eq!(
+            state.array_elements(1, &short, &order),
+            Err(Error::ExponentTooLong { bits: 11, max: 8 })
+        );
+        assert_eq!(
+            state.array_elements(1, &table, &Mpz::new()),
+            Err(Error::InvalidBound)
+        );
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+/// Errors returned by the safe wrappers around the GMPMEE functions.
+///
+/// The raw C functions do not validate their input, so the wrappers reject
+/// parameters that would lead to out of bounds accesses, divisions by zero or
+/// silently wrong results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The number of exponents does not match the number of bases.
+    LengthMismatch { expected: usize, found: usize },
+    /// The modulus is not supported by the operation.
+    InvalidModulus,
+    /// The block width is zero or larger than [`MAX_BLOCK_WIDTH`](crate::MAX_BLOCK_WIDTH).
+    InvalidBlockWidth(usize),
+    /// The batch length is zero.
+    InvalidBatchLen(usize),
+    /// The memory budget does not fit a single block of the given bytes.
+    MemoryBudget { required: usize, budget: usize },
+    /// The spacing of a comb is zero.
+    InvalidSpacing(usize),
+    /// The expected exponent bit length is zero.
+    InvalidExponentBitlen(usize),
+    /// An exponent is negative, which a constant-time function does not support.
+    NegativeExponent,
+    /// A base with a negative exponent is not invertible modulo the modulus.
+    NotInvertible,
+    /// An exponent has more bits than a constant-time table supports.
+    ExponentTooLong { bits: usize, max: usize },
+    /// The upper bound of a random integer is not positive.
+    InvalidBound,
+    /// A string could not be parsed as an integer.
+    ParseInteger,
+    /// A tuning profile or grid is empty, unsorted or has invalid block widths,
+    /// or a grid has a zero exponent bit length.
+    InvalidProfile,
+    /// A thread pool could not be built.
+    ThreadPool,
+    /// Serialized data is truncated, corrupt, or not of the expected kind.
+    InvalidFormat,
+    /// Serialized data has a format version this crate cannot read.
+    UnsupportedVersion(u32),
+    /// Serialized data was computed for other parameters than the expected ones.
+    ParameterMismatch,
+    /// Serialized data cannot be used in place on this target.
+    IncompatibleLayout,
+    /// Reading or writing serialized data failed.
+    Io(std::io::ErrorKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::LengthMismatch { expected, found } => {
+                write!(f, "expected {} exponents, found {}", expected, found)
+            }
+            Error::InvalidModulus => write!(f, "invalid modulus"),
+            Error::InvalidBlockWidth(w) => write!(f, "invalid block width {}", w),
+            Error::InvalidBatchLen(n) => write!(f, "invalid batch length {}", n),
+            Error::MemoryBudget { required, budget } => write!(
+                f,
+                "memory budget of {} bytes below the {} bytes of a single block",
+                budget, required
+            ),
+            Error::InvalidSpacing(n) => write!(f, "invalid comb spacing {}", n),
+            Error::InvalidExponentBitlen(n) => write!(f, "invalid exponent bit length {}", n),
+            Error::NegativeExponent => write!(f, "negative exponent"),
+            Error::NotInvertible => write!(f, "base not invertible modulo the modulus"),
+            Error::ExponentTooLong { bits, max } => {
+                write!(f, "exponent of {} bits longer than {} bits", bits, max)
+            }
+            Error::InvalidBound => write!(f, "invalid bound"),
+            Error::ParseInteger => write!(f, "invalid integer string"),
+            Error::InvalidProfile => write!(f, "invalid tuning profile"),
+            Error::ThreadPool => write!(f, "failed to build thread pool"),
+            Error::InvalidFormat => write!(f, "invalid or corrupt serialized data"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            Error::ParameterMismatch => write!(f, "serialized data does not match the parameters"),
+            Error::IncompatibleLayout => {
+                write!(f, "serialized data cannot be used in place on this target")
+            }
+            Error::Io(kind) => write!(f, "i/o error: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.kind())
+    }
+}
+
+/// Result type of the safe wrappers.
+pub type Result<T> = std::result::Result<T, Error>;
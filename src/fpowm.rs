@@ -0,0 +1,393 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::error::{Error, Result};
+use crate::gmpmee::{
+    gmpmee_fpowm, gmpmee_fpowm_clear, gmpmee_fpowm_init_precomp, gmpmee_fpowm_tab,
+};
+use crate::mpz::{AsMpz, Mpz};
+use crate::serial::{Decoder, Encoder};
+use crate::signed::{fpowm_signed, is_negative};
+use crate::spowm::{check_block_width, check_modulus, clone_raw, subtables, subtables_mut};
+#[cfg(feature = "rayon")]
+use crate::{gmpmee::gmpmee_fpowm_init, spowm::par_precomp};
+use gmp_mpfr_sys::gmp;
+use std::io::{Read, Write};
+use std::mem::MaybeUninit;
+
+const MAGIC: &[u8; 8] = b"GMPMEEFP";
+
+pub(crate) fn check_odd_modulus<M: AsMpz>(modulus: &M) -> Result<()> {
+    check_modulus(modulus)?;
+    match unsafe { gmp::mpz_odd_p(modulus.as_raw()) } {
+        0 => Err(Error::InvalidModulus),
+        _ => Ok(()),
+    }
+}
+
+/// Table for fixed base exponentiation.
+///
+/// Owns a [`gmpmee_fpowm_tab`], filled with [`gmpmee_fpowm_init_precomp`] on
+/// creation and cleared with [`gmpmee_fpowm_clear`] on drop. Cloning copies
+/// every entry of the table.
+///
+/// The table is `Sync`, so that it can be shared, for instance in an `Arc`, by
+/// threads computing exponentiations of the same base.
+#[derive(Debug)]
+pub struct FpowmTable {
+    raw: gmpmee_fpowm_tab,
+}
+
+// The table owns all its integers and is only read after the precomputation:
+// `gmpmee_fpowm` does not modify it, so it can be shared between threads.
+unsafe impl Send for FpowmTable {}
+unsafe impl Sync for FpowmTable {}
+
+impl FpowmTable {
+    /// Allocates a table for the given base and modulus and performs the
+    /// precomputation.
+    ///
+    /// The exponent is split in `block_width` parts of
+    /// `ceil(exponent_bitlen / block_width)` bits. Longer exponents are still
+    /// accepted by [`pow`](Self::pow), but are slower.
+    ///
+    /// The modulus must be odd and greater than one, the block width must lie in
+    /// `1..=MAX_BLOCK_WIDTH` and the exponent bit length must not be zero.
+    pub fn new<B: AsMpz, M: AsMpz>(
+        base: &B,
+        modulus: &M,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        check_odd_modulus(modulus)?;
+        check_block_width(block_width)?;
+        if exponent_bitlen == 0 {
+            return Err(Error::InvalidExponentBitlen(exponent_bitlen));
+        }
+        unsafe {
+            let mut raw = MaybeUninit::uninit();
+            gmpmee_fpowm_init_precomp(
+                raw.as_mut_ptr(),
+                base.as_raw(),
+                modulus.as_raw(),
+                block_width as _,
+                exponent_bitlen as _,
+            );
+            Ok(Self {
+                raw: raw.assume_init(),
+            })
+        }
+    }
+
+    /// Same as [`new`](Self::new), with the precomputation run on the current
+    /// rayon thread pool. The table is identical.
+    ///
+    /// The powers `base^(2^(i * stretch))` are computed in sequence, and the
+    /// products of the powers concurrently.
+    #[cfg(feature = "rayon")]
+    pub fn par_new<B: AsMpz, M: AsMpz>(
+        base: &B,
+        modulus: &M,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        check_odd_modulus(modulus)?;
+        check_block_width(block_width)?;
+        if exponent_bitlen == 0 {
+            return Err(Error::InvalidExponentBitlen(exponent_bitlen));
+        }
+        let mut raw = unsafe {
+            let mut raw = MaybeUninit::uninit();
+            gmpmee_fpowm_init(
+                raw.as_mut_ptr(),
+                modulus.as_raw(),
+                block_width as _,
+                exponent_bitlen as _,
+            );
+            raw.assume_init()
+        };
+        let mut exponent = Mpz::new();
+        let mut bases = vec![Mpz::new(); block_width];
+        unsafe {
+            gmp::mpz_setbit(exponent.as_raw_mut(), raw.stretch as _);
+            gmp::mpz_set(bases[0].as_raw_mut(), base.as_raw());
+            for i in 1..block_width {
+                let (previous, current) = bases.split_at_mut(i);
+                gmp::mpz_powm(
+                    current[0].as_raw_mut(),
+                    previous[i - 1].as_raw(),
+                    exponent.as_raw(),
+                    modulus.as_raw(),
+                );
+            }
+        }
+        par_precomp(&mut raw.spowm_table, &bases);
+        Ok(Self { raw })
+    }
+
+    /// Number of parts the exponent is split into.
+    pub fn block_width(&self) -> usize {
+        self.raw.spowm_table.block_width as usize
+    }
+
+    /// Number of bits of each part of the exponent.
+    pub fn stretch(&self) -> usize {
+        self.raw.stretch as usize
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        unsafe { Mpz::from_raw_ref(&self.raw.spowm_table.modulus) }
+    }
+
+    /// Products of the bases `b^(2^(i * stretch))`, indexed by the bits of the
+    /// exponent taken at a stride of `stretch`.
+    pub(crate) fn entries(&self) -> &[Mpz] {
+        subtables(&self.raw.spowm_table).next().unwrap_or_default()
+    }
+
+    /// Pointer to the underlying table, valid as long as `self`.
+    pub fn as_raw(&self) -> *const gmpmee_fpowm_tab {
+        &self.raw
+    }
+
+    /// Computes the base to the power `exponent` modulo the modulus.
+    ///
+    /// A negative exponent inverts the power, or returns
+    /// [`Error::NotInvertible`](crate::Error::NotInvertible) if the base is not
+    /// invertible modulo the modulus.
+    pub fn pow<E: AsMpz>(&self, exponent: &E) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponent)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponent: &E) -> Result<()> {
+        if is_negative(exponent) {
+            return fpowm_signed(rop, exponent, self.modulus(), |rop, exponent| {
+                self.pow_into(rop, exponent)
+            });
+        }
+        unsafe { gmpmee_fpowm(rop.as_raw_mut(), &self.raw, exponent.as_raw()) };
+        Ok(())
+    }
+
+    /// Computes the base to the power of each exponent modulo the modulus.
+    ///
+    /// With the `rayon` feature, the exponents are processed in parallel on
+    /// the current rayon thread pool, all reading the same table. Negative
+    /// exponents are handled as in [`pow`](Self::pow).
+    pub fn pow_many<E: AsMpz + Sync>(&self, exponents: &[E]) -> Result<Vec<Mpz>> {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            exponents.par_iter().map(|e| self.pow(e)).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        exponents.iter().map(|e| self.pow(e)).collect()
+    }
+}
+
+impl FpowmTable {
+    /// Serializes the table: block width, stretch, modulus and every entry,
+    /// in a versioned and checksummed binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new(MAGIC);
+        encoder.u64(self.block_width());
+        encoder.u64(self.stretch());
+        encoder.mpz(self.modulus());
+        encoder.u64(self.entries().len());
+        for entry in self.entries() {
+            encoder.mpz(entry);
+        }
+        encoder.finish()
+    }
+
+    /// Writes the serialized table to `writer`, see [`to_bytes`](Self::to_bytes).
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Loads a table serialized with [`to_bytes`](Self::to_bytes), without
+    /// recomputing it.
+    ///
+    /// The parameters are those given to [`new`](Self::new) and are checked in
+    /// the same way. Data that is truncated, corrupt or of another format
+    /// version is rejected, and so is a table built for another modulus, block
+    /// width, stretch or base.
+    pub fn from_bytes<B: AsMpz, M: AsMpz>(
+        bytes: &[u8],
+        base: &B,
+        modulus: &M,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        check_odd_modulus(modulus)?;
+        check_block_width(block_width)?;
+        if exponent_bitlen == 0 {
+            return Err(Error::InvalidExponentBitlen(exponent_bitlen));
+        }
+        let mut decoder = Decoder::new(MAGIC, bytes)?;
+        let stored_width = decoder.u64()?;
+        let stored_stretch = decoder.u64()?;
+        let mut stored = Mpz::new();
+        decoder.mpz(&mut stored)?;
+        if stored_width != block_width
+            || stored_stretch != exponent_bitlen.div_ceil(block_width)
+            || unsafe { gmp::mpz_cmp(stored.as_raw(), modulus.as_raw()) } != 0
+        {
+            return Err(Error::ParameterMismatch);
+        }
+        if decoder.u64()? != 1 << block_width {
+            return Err(Error::InvalidFormat);
+        }
+        let mut table = unsafe {
+            let mut raw = MaybeUninit::uninit();
+            crate::gmpmee::gmpmee_fpowm_init(
+                raw.as_mut_ptr(),
+                modulus.as_raw(),
+                block_width as _,
+                exponent_bitlen as _,
+            );
+            Self {
+                raw: raw.assume_init(),
+            }
+        };
+        for entry in subtables_mut(&mut table.raw.spowm_table).flatten() {
+            decoder.mpz(entry)?;
+            if *entry >= stored {
+                return Err(Error::InvalidFormat);
+            }
+        }
+        decoder.finish()?;
+        unsafe { gmp::mpz_mod(stored.as_raw_mut(), base.as_raw(), modulus.as_raw()) };
+        match table.entries()[1] == stored {
+            true => Ok(table),
+            false => Err(Error::ParameterMismatch),
+        }
+    }
+
+    /// Reads a serialized table from `reader` to its end, see
+    /// [`from_bytes`](Self::from_bytes).
+    pub fn read_from<R: Read, B: AsMpz, M: AsMpz>(
+        mut reader: R,
+        base: &B,
+        modulus: &M,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes, base, modulus, block_width, exponent_bitlen)
+    }
+}
+
+impl Clone for FpowmTable {
+    fn clone(&self) -> Self {
+        Self {
+            raw: gmpmee_fpowm_tab {
+                spowm_table: unsafe { clone_raw(&self.raw.spowm_table) },
+                stretch: self.raw.stretch,
+            },
+        }
+    }
+}
+
+impl Drop for FpowmTable {
+    fn drop(&mut self) {
+        unsafe { gmpmee_fpowm_clear(&mut self.raw) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Rng, powm};
+
+    #[test]
+    fn pow_matches_powm() {
+        let mut rng = Rng::new(5);
+        for (block_width, exponent_bitlen) in [(1, 64), (2, 100), (4, 256), (8, 255), (6, 7)] {
+            let modulus = rng.odd_modulus(256);
+            let base = rng.below(&modulus);
+            let table = FpowmTable::new(&base, &modulus, block_width, exponent_bitlen).unwrap();
+            assert_eq!(table.block_width(), block_width);
+            assert_eq!(table.stretch(), exponent_bitlen.div_ceil(block_width));
+            assert_eq!(table.modulus(), &modulus);
+            // Exponents of the expected length, zero, and longer ones.
+            for bits in [0, 1, exponent_bitlen, exponent_bitlen + 40] {
+                let exponent = rng.bits(bits);
+                assert_eq!(
+                    table.pow(&exponent).unwrap(),
+                    powm(&base, &exponent, &modulus)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let base = Mpz::from(3u32);
+        let modulus = Mpz::from(101u32);
+        for block_width in [0, crate::MAX_BLOCK_WIDTH + 1] {
+            assert_eq!(
+                FpowmTable::new(&base, &modulus, block_width, 64).unwrap_err(),
+                Error::InvalidBlockWidth(block_width)
+            );
+        }
+        for modulus in [Mpz::new(), Mpz::from(1u32), Mpz::from(100u32)] {
+            assert_eq!(
+                FpowmTable::new(&base, &modulus, 2, 64).unwrap_err(),
+                Error::InvalidModulus
+            );
+        }
+        assert_eq!(
+            FpowmTable::new(&base, &modulus, 2, 0).unwrap_err(),
+            Error::InvalidExponentBitlen(0)
+        );
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let mut rng = Rng::new(6);
+        let modulus = rng.odd_modulus(192);
+        let base = rng.below(&modulus);
+        let exponent = rng.bits(128);
+        let table = FpowmTable::new(&base, &modulus, 4, 128).unwrap();
+        let expected = table.pow(&exponent).unwrap();
+        let clone = table.clone();
+        drop(table);
+        assert_eq!(clone.block_width(), 4);
+        assert_eq!(clone.stretch(), 32);
+        assert_eq!(clone.pow(&exponent).unwrap(), expected);
+        assert_eq!(clone.pow_many(&[exponent]).unwrap(), [expected]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_new_matches_new() {
+        let mut rng = Rng::new(14);
+        for (block_width, exponent_bitlen) in [(1, 64), (3, 100), (8, 255)] {
+            let modulus = rng.odd_modulus(256);
+            let base = rng.below(&modulus);
+            let table = FpowmTable::new(&base, &modulus, block_width, exponent_bitlen).unwrap();
+            let par = FpowmTable::par_new(&base, &modulus, block_width, exponent_bitlen).unwrap();
+            assert_eq!(par.to_bytes(), table.to_bytes());
+        }
+    }
+}
@@ -19,7 +19,7 @@ use gmp_mpfr_sys::gmp::{mpz_ptr, mpz_srcptr, mpz_t, randstate_ptr, size_t};
 
 #[doc = " Stores the tables of precomputed products of subsets of the\n bases. Each table contains the precomputed products for a range of\n a given width of the bases."]
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug)]
 pub struct gmpmee_spowm_tab {
     #[doc = "< Total number of bases/exponents."]
     pub len: size_t,
@@ -51,29 +51,29 @@ const _: () = {
 #[link(name = "gmpmee", kind = "static")]
 unsafe extern "C" {
     #[doc = " Allocates and initializes a table for the given modulus, block\n width, and total number of bases.\n\n @param table Table to be initialized\n @param len Number of bases in the simultaneous exponentiation.\n @param modulus Modulus.\n @param block_width Number of bases used to build each subtable."]
-    fn gmpmee_spowm_init(
+    pub fn gmpmee_spowm_init(
         table: *mut gmpmee_spowm_tab,
         len: usize,
-        modulus: mpz_ptr,
+        modulus: mpz_srcptr,
         block_width: usize,
     );
 
     #[doc = " Frees the memory allocated by table.\n\n @param table Table to be deallocated."]
-    fn gmpmee_spowm_clear(table: *mut gmpmee_spowm_tab);
+    pub fn gmpmee_spowm_clear(table: *mut gmpmee_spowm_tab);
 
     #[doc = " Fills the table with precomputed values using the given bases. The\n array of bases must be of the length for which the table was\n allocated.\n\n @param table Table to be initialized.\n @param bases Bases for which precomputation is performed."]
-    fn gmpmee_spowm_precomp(table: *mut gmpmee_spowm_tab, bases: mpz_srcptr);
+    pub fn gmpmee_spowm_precomp(table: *mut gmpmee_spowm_tab, bases: mpz_srcptr);
 
     #[doc = " Computes a simultaneous exponentiation using the given table and\n exponents. The number of exponents must match the number of bases\n that was used during precomputation.\n\n @param rop Destination of result.\n @param table Precomputed table representing the bases used.\n @param exponents Exponents used in simultaneous exponentiation."]
-    fn gmpmee_spowm_table(rop: mpz_ptr, table: *mut gmpmee_spowm_tab, exponents: mpz_srcptr);
+    pub fn gmpmee_spowm_table(rop: mpz_ptr, table: *const gmpmee_spowm_tab, exponents: mpz_srcptr);
 
     #[doc = " Computes a simultaneous exponentiation. Precomputation is performed\n in blocks of the given width in batches of the given batch size.\n\n @param rop Destination of result.\n @param bases Bases for which precomputation is performed.\n @param exponents Exponents used in simultaneous exponentiation.\n @param len Number of bases in the simultaneous exponentiation.\n @param modulus Modulus.\n @param block_width Number of bases used to build each subtable.\n @param batch_len Number of bases in each batch, where each batch\n is computed independently."]
-    fn gmpmee_spowm_block_batch(
+    pub fn gmpmee_spowm_block_batch(
         rop: mpz_ptr,
         bases: mpz_srcptr,
         exponents: mpz_srcptr,
         len: size_t,
-        modulus: mpz_ptr,
+        modulus: mpz_srcptr,
         block_width: size_t,
         batch_len: size_t,
     );
@@ -86,11 +86,20 @@ unsafe extern "C" {
         len: size_t,
         modulus: mpz_srcptr,
     );
+
+    #[doc = " Naively computes the exponentiated product of the bases to the\n powers of the exponents modulo the given modulus. This is used for\n debugging.\n\n @param rop Destination of result.\n @param bases Bases.\n @param exponents Exponents. used in simultaneous exponentiation.\n @param len Number of bases.\n @param modulus Modulus."]
+    pub fn gmpmee_spowm_naive(
+        rop: mpz_ptr,
+        bases: mpz_srcptr,
+        exponents: mpz_srcptr,
+        len: size_t,
+        modulus: mpz_srcptr,
+    );
 }
 
 #[doc = " Stores a fixed base exponentiation table."]
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug)]
 pub struct gmpmee_fpowm_tab {
     #[doc = "< We exploit simultaneous exp. table."]
     pub spowm_table: gmpmee_spowm_tab,
@@ -138,7 +147,7 @@ unsafe extern "C" {
 
 #[doc = " Stores state inbetween individual invokations of the Miller-Rabin\n test and keeps allocated space."]
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug)]
 pub struct gmpmee_millerrabin_state {
     #[doc = "< integer to be tested"]
     pub n: mpz_t,
@@ -214,7 +223,7 @@ unsafe extern "C" {
 
 #[doc = " Stores the states needed for using the Miller-Rabin test for\n testing for safe-primality."]
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug)]
 pub struct gmpmee_millerrabin_safe_state {
     #[doc = " State of the integer <i>n</i> to be tested."]
     pub nstate: gmpmee_millerrabin_state,
@@ -286,48 +295,22 @@ unsafe extern "C" {
     pub fn mpz_probab_safe_prime_p_next(rop: mpz_ptr, n: mpz_ptr, reps: ::std::ffi::c_int);
 }
 
-/* Not used as internal functions of GMPMEE
-unsafe extern "C" {
-    #[doc = " Allocates an array of <code>len</code> <code>mpz_ptr</code>.\n\n @param len Number of elements in array.\n @return Pointer to allocated array."]
-    pub fn gmpmee_array_alloc(len: usize) -> *mut mpz_ptr;
-}
-unsafe extern "C" {
-    #[doc = " Allocates and initializes an array of <code>len</code>\n <code>mpz_ptr</code>.\n\n @param len Number of elements in array.\n @return Pointer to allocated array."]
-    pub fn gmpmee_array_alloc_init(len: usize) -> *mut mpz_ptr;
-}
-unsafe extern "C" {
-    #[doc = " Clears and deallocates the array containing <code>len</code>\n <code>mpz_ptr</code>.\n\n @param a Array to be cleared and deallocated.\n @param len Number of elements in array."]
-    pub fn gmpmee_array_clear_dealloc(a: *mut mpz_ptr, len: usize);
-}
+#[link(name = "gmpmee", kind = "static")]
 unsafe extern "C" {
-    #[doc = " Fills the array rop containing <code>len</code> <code>mpz_ptr</code>\n with random positive <code>n</code>-bit integers. <b>WARNING! The\n pseudo-random generator of GMP used as a subroutine is *not*\n cryptographically secure.</b>\n\n @param rop Destination of result.\n @param len Number of elements in array.\n @param state State of pseudo-random generator.\n @param n Number of bits in each random integer."]
+    #[doc = " Allocates an array of <code>len</code> <code>mpz_t</code>.\n\n @param len Number of elements in array.\n @return Pointer to allocated array."]
+    pub fn gmpmee_array_alloc(len: usize) -> *mut mpz_t;
+
+    #[doc = " Allocates and initializes an array of <code>len</code>\n <code>mpz_t</code>.\n\n @param len Number of elements in array.\n @return Pointer to allocated array."]
+    pub fn gmpmee_array_alloc_init(len: usize) -> *mut mpz_t;
+
+    #[doc = " Clears and deallocates the array containing <code>len</code>\n <code>mpz_t</code>.\n\n @param a Array to be cleared and deallocated.\n @param len Number of elements in array."]
+    pub fn gmpmee_array_clear_dealloc(a: *mut mpz_t, len: usize);
+
+    #[doc = " Fills the array rop containing <code>len</code> <code>mpz_t</code>\n with random positive <code>n</code>-bit integers. <b>WARNING! The\n pseudo-random generator of GMP used as a subroutine is *not*\n cryptographically secure.</b>\n\n @param rop Destination of result.\n @param len Number of elements in array.\n @param state State of pseudo-random generator.\n @param n Number of bits in each random integer."]
     pub fn gmpmee_array_urandomb(
-        rop: *mut mpz_ptr,
+        rop: *mut mpz_t,
         len: usize,
-        state: *mut __gmp_randstate_struct,
+        state: randstate_ptr,
         n: ::std::ffi::c_ulong,
     );
 }
-pub type __builtin_va_list = [__va_list_tag; 1usize];
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct __va_list_tag {
-    pub gp_offset: ::std::ffi::c_uint,
-    pub fp_offset: ::std::ffi::c_uint,
-    pub overflow_arg_area: *mut ::std::ffi::c_void,
-    pub reg_save_area: *mut ::std::ffi::c_void,
-}
-#[allow(clippy::unnecessary_operation, clippy::identity_op)]
-const _: () = {
-    ["Size of __va_list_tag"][::std::mem::size_of::<__va_list_tag>() - 24usize];
-    ["Alignment of __va_list_tag"][::std::mem::align_of::<__va_list_tag>() - 8usize];
-    ["Offset of field: __va_list_tag::gp_offset"]
-        [::std::mem::offset_of!(__va_list_tag, gp_offset) - 0usize];
-    ["Offset of field: __va_list_tag::fp_offset"]
-        [::std::mem::offset_of!(__va_list_tag, fp_offset) - 4usize];
-    ["Offset of field: __va_list_tag::overflow_arg_area"]
-        [::std::mem::offset_of!(__va_list_tag, overflow_arg_area) - 8usize];
-    ["Offset of field: __va_list_tag::reg_save_area"]
-        [::std::mem::offset_of!(__va_list_tag, reg_save_area) - 16usize];
-};
-  */
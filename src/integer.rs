@@ -0,0 +1,146 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Integration with [rug](https://crates.io/crates/rug), enabled by the `rug` feature.
+//!
+//! [`Integer`] is a transparent wrapper around `mpz_t`, so it implements
+//! [`AsMpz`] and slices of integers are passed to the safe wrappers without
+//! copying. [`RandState`] implements [`RandSource`] for the primality tests.
+//!
+//! The functions of this module return their results as [`Integer`].
+
+use crate::error::Result;
+use crate::mpz::{AsMpz, Mpz};
+use crate::prime::{self, RandSource};
+use crate::{FpowmTable, SpowmTable};
+use gmp_mpfr_sys::gmp::randstate_ptr;
+use rug::Integer;
+use rug::rand::RandState;
+
+unsafe impl AsMpz for Integer {}
+
+unsafe impl RandSource for RandState<'_> {
+    fn as_raw_mut(&mut self) -> randstate_ptr {
+        RandState::as_raw_mut(self)
+    }
+}
+
+impl From<Mpz> for Integer {
+    fn from(value: Mpz) -> Self {
+        unsafe { Integer::from_raw(value.into_raw()) }
+    }
+}
+
+impl From<Integer> for Mpz {
+    fn from(value: Integer) -> Self {
+        unsafe { Mpz::from_raw(value.into_raw()) }
+    }
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus`, see [`crate::spowm()`].
+pub fn spowm(bases: &[Integer], exponents: &[Integer], modulus: &Integer) -> Result<Integer> {
+    let mut rop = Integer::new();
+    crate::spowm(&mut rop, bases, exponents, modulus)?;
+    Ok(rop)
+}
+
+/// Computes the product of the `b_i^e_i` with a precomputed table, see [`SpowmTable::pow`].
+pub fn spowm_table(table: &SpowmTable, exponents: &[Integer]) -> Result<Integer> {
+    let mut rop = Integer::new();
+    table.pow_into(&mut rop, exponents)?;
+    Ok(rop)
+}
+
+/// Computes a fixed base exponentiation, see [`FpowmTable::pow`].
+pub fn fpowm(table: &FpowmTable, exponent: &Integer) -> Result<Integer> {
+    let mut rop = Integer::new();
+    table.pow_into(&mut rop, exponent)?;
+    Ok(rop)
+}
+
+/// Returns `false` if `n` is composite, see [`crate::millerrabin`].
+pub fn is_probably_prime(n: &Integer, reps: u32, rand: &mut RandState<'_>) -> bool {
+    prime::millerrabin(n, reps, rand)
+}
+
+/// Returns `false` if `n` is not a safe prime, see [`crate::millerrabin_safe`].
+pub fn is_probably_safe_prime(n: &Integer, reps: u32, rand: &mut RandState<'_>) -> bool {
+    prime::millerrabin_safe(n, reps, rand)
+}
+
+/// Returns the smallest prime larger than `n`, see [`crate::millerrabin_next`].
+pub fn next_prime(n: &Integer, reps: u32, rand: &mut RandState<'_>) -> Integer {
+    let mut rop = Integer::new();
+    prime::millerrabin_next_into(&mut rop, n, reps, rand);
+    rop
+}
+
+/// Returns the smallest safe prime larger than `n`, see [`crate::millerrabin_safe_next`].
+pub fn next_safe_prime(n: &Integer, reps: u32, rand: &mut RandState<'_>) -> Integer {
+    let mut rop = Integer::new();
+    prime::millerrabin_safe_next_into(&mut rop, n, reps, rand);
+    rop
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        for value in [Integer::ZERO, Integer::from(-7), Integer::from(1) << 300] {
+            let mpz = Mpz::from(value.clone());
+            assert_eq!(mpz.to_string(), value.to_string());
+            assert_eq!(Integer::from(mpz), value);
+        }
+    }
+
+    #[test]
+    fn exponentiations() {
+        let modulus = (Integer::from(1) << 255) - 19;
+        let bases = [Integer::from(2), Integer::from(3), Integer::from(-5)];
+        let exponents = [
+            Integer::from(100),
+            Integer::from(-3),
+            Integer::from(1) << 80,
+        ];
+        let mut expected = Integer::from(1);
+        for (b, e) in bases.iter().zip(&exponents) {
+            expected *= b.clone().pow_mod(e, &modulus).unwrap();
+            expected %= &modulus;
+        }
+        assert_eq!(spowm(&bases, &exponents, &modulus).unwrap(), expected);
+        let table = SpowmTable::new(&modulus, &bases, 2).unwrap();
+        assert_eq!(spowm_table(&table, &exponents).unwrap(), expected);
+        let table = FpowmTable::new(&bases[1], &modulus, 4, 128).unwrap();
+        assert_eq!(
+            fpowm(&table, &exponents[2]).unwrap(),
+            bases[1].clone().pow_mod(&exponents[2], &modulus).unwrap()
+        );
+    }
+
+    #[test]
+    fn primes() {
+        let mut rand = RandState::new();
+        assert!(is_probably_prime(&Integer::from(65537), 20, &mut rand));
+        assert!(!is_probably_prime(&Integer::from(561), 20, &mut rand));
+        assert!(!is_probably_prime(&Integer::from(-3), 20, &mut rand));
+        assert!(is_probably_safe_prime(&Integer::from(107), 20, &mut rand));
+        assert!(!is_probably_safe_prime(&Integer::from(97), 20, &mut rand));
+        assert_eq!(next_prime(&Integer::from(89), 20, &mut rand), 97);
+        assert_eq!(next_safe_prime(&Integer::from(11), 20, &mut rand), 23);
+    }
+}
@@ -29,6 +29,88 @@
 //! Unlike in the C libraries, the types (e.g. `gmpmee_spowm_tab`, `gmpmee_fpowm_tab`) are defined directly as structs,
 //! not as single-element arrays.
 //!
+//! The tables and Miller-Rabin states are neither `Copy` nor `Clone`, since they own the memory of their integers:
+//! clearing a bitwise copy as well as the original is a double free. The safe wrappers implement `Clone` with a deep
+//! copy instead.
+//!
+//! # Safe wrappers
+//!
+//! On top of the raw bindings, the crate provides safe types owning the GMPMEE tables and freeing them on drop:
+//!
+//! - [`SpowmTable`]: simultaneous exponentiation with precomputed products of the bases.
+//! - [`FpowmTable`]: fixed base exponentiation.
+//! - [`SecSpowmTable`] and [`SecFpowmTable`]: constant-time counterparts of the two tables, for secret exponents.
+//! - [`CombTable`]: fixed base exponentiation with the Lim-Lee comb method, choosing the width of the subtables and the
+//!   number of squarings separately. [`FixedBaseTable`] holds either kind of fixed base table, chosen at runtime.
+//! - [`MultiFpowmTable`]: exponentiation of several fixed bases, such as the generators of Pedersen commitments,
+//!   splitting the exponents as a [`FpowmTable`] and sharing one chain of squarings between the bases.
+//! - [`MontSpowmTable`] and [`MontFpowmTable`]: faster counterparts of the two tables for odd moduli, computing in
+//!   the Montgomery domain on the `mpn` functions of GMP, with the same results. The two tables themselves still
+//!   reduce with `mpz_mod`.
+//! - [`MpzArray`]: array of integers allocated by GMPMEE, usable wherever a slice of integers is expected.
+//!
+//! Integers are passed as [`Mpz`], or as any other type implementing [`AsMpz`], without copying.
+//! Invalid parameters are reported with [`Error`].
+//!
+//! Unlike the C functions, which misread them, the safe wrappers accept negative exponents, except for the constant-time
+//! ones. A negative exponent inverts its base: the bases with negative exponents are raised to the absolute values of
+//! their exponents in a product of their own, inverted once at the end, and [`Error::NotInvertible`] is returned if one
+//! of them is not invertible modulo the modulus.
+//!
+//! [`spowm_block_batch`] bounds the memory of a simultaneous exponentiation of a long vector with a [`BlockBatch`],
+//! given explicitly or derived from a memory budget.
+//!
+//! An [`FpowmTable`] can be saved with [`FpowmTable::write_to`] and loaded back with [`FpowmTable::read_from`],
+//! which checks the format version, the checksum and the parameters of the table. The same goes for a
+//! [`SpowmTable`], whose file holds the number of bases, the block width and every subtable.
+//!
+//! [`gmpmee_spowm`] picks its block width from a table tuned for one platform. [`TuningGrid::tune`] times the block
+//! widths on the current machine and returns a [`TuningProfile`], which [`set_tuning_profile`] installs for
+//! [`spowm()`] in place of the built-in table. The profile does not reach [`gmpmee_spowm`] nor the functions taking
+//! an explicit block width, such as [`SpowmTable::new`] and [`spowm_block_batch`]. `cargo run --release --example
+//! tune` tunes and saves a profile.
+//! [`spowm_with_strategy`] takes the block width from a [`SpowmStrategy`] instead, and returns the parameters it used.
+//!
+//! [`spowm_bucket`] computes the product with the bucket method of Pippenger, one multiplication per base and per
+//! window of exponent bits, for very long vectors. [`spowm()`] switches to it from [`BUCKET_THRESHOLD`] bases on, when
+//! [`Cost`] predicts fewer multiplications than the block method for the length of the exponents, while the raw
+//! [`gmpmee_spowm`] is unchanged. `cargo run --release --example bucket` checks it against [`spowm_naive`] and
+//! times it against [`gmpmee_spowm`].
+//!
+//! [`spowm2`] and [`spowm3`] compute `g^a * h^b` and its three-base counterpart, as in Pedersen commitments and
+//! Chaum-Pedersen verifications, with the sliding windows of the exponents interleaved along one chain of squarings
+//! and without the block table of [`gmpmee_spowm`]. [`spowm()`] uses them for up to [`INTERLEAVED_MAX_LEN`] bases
+//! and an odd modulus.
+//!
+//! [`Cost`] predicts the modular multiplications and the table memory of [`spowm_block_batch`], of [`spowm_bucket`],
+//! of a [`FpowmTable`], of a [`MultiFpowmTable`], of a [`CombTable`] and of a plain `mpz_powm`, to choose between
+//! them before computing.
+//!
+//! `cargo run --release --example montgomery` times the tables against their Montgomery counterparts for 2048-,
+//! 3072- and 4096-bit moduli. `cargo run --release --example comb` compares the fixed base tables by table memory
+//! for 256-bit exponents over a 3072-bit modulus.
+//!
+//! [`sec_spowm`] is the constant-time counterpart of [`spowm()`] and [`spowm_naive`] computes each exponentiation
+//! separately, as a reference for the other implementations.
+//!
+//! The one-shot functions [`spowm()`], [`millerrabin`], [`millerrabin_next`], [`millerrabin_safe`] and
+//! [`millerrabin_safe_next`] wrap the corresponding GMPMEE functions. The primality tests draw their bases from a
+//! [`RandSource`].
+//!
+//! # Optional features
+//!
+//! - `rug`: `rug::Integer` implements [`AsMpz`] and `rug::rand::RandState` implements [`RandSource`]. The module
+//!   [`integer`] provides helpers returning `Integer`.
+//! - `num-bigint`: the module [`biguint`] converts `num_bigint::BigUint` to and from [`Mpz`], and runs the same
+//!   operations on `BigUint` with reusable conversion buffers.
+//! - `rand`: the module [`csprng`] provides a [`RandSource`] drawing its bits from any `rand_core::CryptoRngCore`,
+//!   or from the entropy source of the operating system, and generates secret exponents and subgroup elements.
+//! - `rayon`: the module [`parallel`] computes the batches of a simultaneous exponentiation on a thread pool,
+//!   [`FpowmTable::pow_many`] processes its exponents in parallel,
+//!   and [`SpowmTable::par_new`] and [`FpowmTable::par_new`] fill the tables in parallel.
+//! - `mmap`: [`SpowmTable::map_file`] maps a saved table read-only and uses its entries in place, so that
+//!   several processes can share one file.
+//!
 //! # Using gmpmee-sys
 //!
 //!  The gmpmee-sys crate is available on crates.io.
@@ -64,6 +146,52 @@
 //!
 //! The gmpee-sys crate is free software: you can redistribute it and/or modify it under the terms of the GNU Lesser General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version. See the full text of the [LICENSE](LICENSE.md) for details.
 
+mod array;
+#[cfg(feature = "num-bigint")]
+pub mod biguint;
+mod bucket;
+mod comb;
+mod cost;
+#[cfg(feature = "rand")]
+pub mod csprng;
+mod error;
+mod fpowm;
 mod gmpmee;
+#[cfg(feature = "rug")]
+pub mod integer;
+mod mont;
+mod mpz;
+mod multi;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+mod prime;
+mod sec;
+mod serial;
+mod shamir;
+mod signed;
+mod spowm;
+#[cfg(test)]
+mod testing;
+mod tune;
 
+pub use array::MpzArray;
+pub use bucket::{BUCKET_THRESHOLD, bucket_window, spowm_bucket};
+pub use comb::{CombTable, FixedBaseMethod, FixedBaseTable};
+pub use cost::Cost;
+pub use error::{Error, Result};
+pub use fpowm::FpowmTable;
 pub use gmpmee::*;
+pub use mont::{MontFpowmTable, MontSpowmTable};
+pub use mpz::{AsMpz, Mpz};
+pub use multi::MultiFpowmTable;
+pub use prime::{
+    RandSource, millerrabin, millerrabin_next, millerrabin_next_into, millerrabin_safe,
+    millerrabin_safe_next, millerrabin_safe_next_into,
+};
+pub use sec::{SecFpowmTable, SecSpowmTable, sec_spowm};
+pub use shamir::{INTERLEAVED_MAX_LEN, spowm2, spowm3};
+pub use spowm::{BlockBatch, MAX_BLOCK_WIDTH, SpowmTable, spowm, spowm_block_batch, spowm_naive};
+pub use tune::{
+    SpowmStrategy, TuningGrid, TuningProfile, TuningRow, set_tuning_profile, spowm_with_strategy,
+    tuning_profile,
+};
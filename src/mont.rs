@@ -0,0 +1,498 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::error::{Error, Result};
+use crate::fpowm::{FpowmTable, check_odd_modulus};
+use crate::mpz::{AsMpz, Mpz, limb_bit, read_limbs, write_limbs};
+use crate::signed::{fpowm_signed, has_negative, is_negative, pow_signed};
+use crate::spowm::{SpowmTable, check_len, max_bitlen};
+use gmp_mpfr_sys::gmp::{self, limb_t};
+
+const LIMB_BITS: usize = gmp::LIMB_BITS as usize;
+
+/// Montgomery multiplication of residues of `n` limbs modulo an odd modulus,
+/// with `R = 2^(n * LIMB_BITS)`.
+///
+/// Products are computed with `mpn_mul_n` or `mpn_sqr` and reduced with one
+/// `mpn_addmul_1` per limb, so that no division and no allocation takes place
+/// between the conversions.
+pub(crate) struct MontModulus {
+    limbs: Vec<limb_t>,
+    /// `-limbs[0]^-1` modulo `2^LIMB_BITS`.
+    inv: limb_t,
+    prod: Vec<limb_t>,
+}
+
+impl MontModulus {
+    /// The modulus must be odd and greater than one.
+    pub(crate) fn new<M: AsMpz>(modulus: &M) -> Self {
+        let n = unsafe { gmp::mpz_size(modulus.as_raw()) };
+        let limbs = read_limbs(modulus, n);
+        // Newton iteration, each step doubles the number of correct low bits,
+        // starting from 3 since `m0 * m0 = 1` modulo 8 for odd `m0`.
+        let m0 = limbs[0];
+        let mut inv = m0;
+        for _ in 0..5 {
+            inv = inv.wrapping_mul((2 as limb_t).wrapping_sub(m0.wrapping_mul(inv)));
+        }
+        Self {
+            limbs,
+            inv: inv.wrapping_neg(),
+            prod: vec![0; 2 * n],
+        }
+    }
+
+    /// Number of limbs of the residues.
+    pub(crate) fn len(&self) -> usize {
+        self.limbs.len()
+    }
+
+    /// Returns `op * R` modulo the modulus.
+    pub(crate) fn to_mont<T: AsMpz>(&self, op: &T) -> Vec<limb_t> {
+        let mut modulus = Mpz::new();
+        write_limbs(&mut modulus, &self.limbs);
+        let mut r = Mpz::new();
+        unsafe {
+            gmp::mpz_mul_2exp(r.as_raw_mut(), op.as_raw(), (self.len() * LIMB_BITS) as _);
+            gmp::mpz_mod(r.as_raw_mut(), r.as_raw(), modulus.as_raw());
+        }
+        read_limbs(&r, self.len())
+    }
+
+    /// Returns `R` modulo the modulus, the Montgomery form of one.
+    pub(crate) fn one(&self) -> Vec<limb_t> {
+        self.to_mont(&Mpz::from(1u32))
+    }
+
+    /// Sets `rop` to the integer of Montgomery form `op`.
+    pub(crate) fn set_from_mont<R: AsMpz>(&mut self, rop: &mut R, op: &[limb_t]) {
+        let n = self.len();
+        let mut limbs = op.to_vec();
+        self.prod[..n].copy_from_slice(op);
+        self.prod[n..].fill(0);
+        self.reduce(&mut limbs);
+        write_limbs(rop, &limbs);
+    }
+
+    /// Sets `rp` to `rp * bp / R` modulo the modulus.
+    pub(crate) fn mul(&mut self, rp: &mut [limb_t], bp: &[limb_t]) {
+        let n = self.len();
+        unsafe { gmp::mpn_mul_n(self.prod.as_mut_ptr(), rp.as_ptr(), bp.as_ptr(), n as _) };
+        self.reduce(rp);
+    }
+
+    /// Sets `rp` to `rp^2 / R` modulo the modulus.
+    pub(crate) fn sqr(&mut self, rp: &mut [limb_t]) {
+        let n = self.len();
+        unsafe { gmp::mpn_sqr(self.prod.as_mut_ptr(), rp.as_ptr(), n as _) };
+        self.reduce(rp);
+    }
+
+    /// Sets `rp` to `prod / R` modulo the modulus, for `prod` below
+    /// `modulus * R`.
+    fn reduce(&mut self, rp: &mut [limb_t]) {
+        let n = self.len();
+        let mp = self.limbs.as_ptr();
+        unsafe {
+            let up = self.prod.as_mut_ptr();
+            // Each step clears the low limb, whose slot then keeps the carry
+            // out of the step, to be added to the high half at the end.
+            for i in 0..n {
+                let q = (*up.add(i)).wrapping_mul(self.inv);
+                *up.add(i) = gmp::mpn_addmul_1(up.add(i), mp, n as _, q);
+            }
+            let carry = gmp::mpn_add_n(rp.as_mut_ptr(), up.add(n), up, n as _);
+            if carry != 0 || gmp::mpn_cmp(rp.as_ptr(), mp, n as _) >= 0 {
+                gmp::mpn_sub_n(rp.as_mut_ptr(), rp.as_ptr(), mp, n as _);
+            }
+        }
+    }
+}
+
+/// Returns the entry `which` of a table of residues of `n` limbs.
+fn entry(table: &[limb_t], n: usize, which: usize) -> &[limb_t] {
+    &table[which * n..(which + 1) * n]
+}
+
+/// Table for simultaneous exponentiation in the Montgomery domain.
+///
+/// Holds the subtables of a [`SpowmTable`] in Montgomery form, and runs the
+/// same square-and-multiply as [`SpowmTable::pow`], with Montgomery
+/// multiplications on the `mpn` functions of GMP instead of `mpz_mul` followed
+/// by `mpz_mod`. The accumulator is converted back only at the end, so the
+/// results equal those of [`SpowmTable::pow`].
+///
+/// The modulus must be odd. [`SpowmTable::pow`] and `gmpmee_spowm_table` are
+/// left unchanged and still reduce with `mpz_mod`, for odd moduli too: the
+/// Montgomery arithmetic is only used through this table.
+#[derive(Debug, Clone)]
+pub struct MontSpowmTable {
+    modulus: Mpz,
+    tables: Vec<Vec<limb_t>>,
+    len: usize,
+    block_width: usize,
+}
+
+impl MontSpowmTable {
+    /// Builds a [`SpowmTable`] with the same parameters and converts it, see
+    /// [`SpowmTable::new`]. The modulus must also be odd.
+    pub fn new<M: AsMpz, B: AsMpz>(modulus: &M, bases: &[B], block_width: usize) -> Result<Self> {
+        check_odd_modulus(modulus)?;
+        Self::try_from(&SpowmTable::new(modulus, bases, block_width)?)
+    }
+
+    /// Number of bases.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the table has no bases.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of bases in each block.
+    pub fn block_width(&self) -> usize {
+        self.block_width
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        &self.modulus
+    }
+
+    /// Computes the product of the `b_i^e_i` modulo the modulus.
+    ///
+    /// The number of exponents must be the number of bases. Negative exponents
+    /// are handled as in [`SpowmTable::pow`].
+    pub fn pow<E: AsMpz>(&self, exponents: &[E]) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponents)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponents: &[E]) -> Result<()> {
+        check_len(self.len, exponents.len())?;
+        if has_negative(exponents) {
+            return pow_signed(rop, exponents, &self.modulus, |rop, exponents| {
+                self.pow_into(rop, exponents)
+            });
+        }
+        let bitlen = max_bitlen(exponents);
+        let exponents: Vec<_> = exponents
+            .iter()
+            .map(|e| read_limbs(e, bitlen.div_ceil(LIMB_BITS)))
+            .collect();
+        let mut modulus = MontModulus::new(&self.modulus);
+        let n = modulus.len();
+        let mut acc = modulus.one();
+        for index in (0..bitlen).rev() {
+            modulus.sqr(&mut acc);
+            for (table, block) in self
+                .tables
+                .iter()
+                .zip(exponents.chunks(self.block_width.max(1)))
+            {
+                let mut mask = 0;
+                for e in block.iter().rev() {
+                    mask = (mask << 1) | limb_bit(e, index);
+                }
+                if mask != 0 {
+                    modulus.mul(&mut acc, entry(table, n, mask as usize));
+                }
+            }
+        }
+        modulus.set_from_mont(rop, &acc);
+        Ok(())
+    }
+}
+
+impl TryFrom<&SpowmTable> for MontSpowmTable {
+    type Error = Error;
+
+    /// Converts the subtables to Montgomery form. The modulus of the table must
+    /// be odd.
+    fn try_from(table: &SpowmTable) -> Result<Self> {
+        check_odd_modulus(table.modulus())?;
+        let modulus = MontModulus::new(table.modulus());
+        Ok(Self {
+            modulus: table.modulus().clone(),
+            tables: table
+                .subtables()
+                .map(|t| t.iter().flat_map(|e| modulus.to_mont(e)).collect())
+                .collect(),
+            len: table.len(),
+            block_width: table.block_width(),
+        })
+    }
+}
+
+/// Table for fixed base exponentiation in the Montgomery domain.
+///
+/// Holds the entries of a [`FpowmTable`] in Montgomery form, and runs the same
+/// algorithm as [`FpowmTable::pow`], including for exponents longer than the
+/// table was built for, with Montgomery multiplications like
+/// [`MontSpowmTable`]. Its results equal those of [`FpowmTable::pow`].
+///
+/// As for [`MontSpowmTable`], [`FpowmTable::pow`] and `gmpmee_fpowm` are not
+/// routed through this table.
+#[derive(Debug, Clone)]
+pub struct MontFpowmTable {
+    modulus: Mpz,
+    table: Vec<limb_t>,
+    block_width: usize,
+    stretch: usize,
+}
+
+impl MontFpowmTable {
+    /// Builds a [`FpowmTable`] with the same parameters and converts it, see
+    /// [`FpowmTable::new`].
+    pub fn new<B: AsMpz, M: AsMpz>(
+        base: &B,
+        modulus: &M,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        Ok(Self::from(&FpowmTable::new(
+            base,
+            modulus,
+            block_width,
+            exponent_bitlen,
+        )?))
+    }
+
+    /// Number of parts the exponent is split into.
+    pub fn block_width(&self) -> usize {
+        self.block_width
+    }
+
+    /// Number of bits of each part of the exponent.
+    pub fn stretch(&self) -> usize {
+        self.stretch
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        &self.modulus
+    }
+
+    /// Computes the base to the power `exponent` modulo the modulus.
+    ///
+    /// A negative exponent inverts the power, or returns
+    /// [`Error::NotInvertible`](crate::Error::NotInvertible) if the base is not
+    /// invertible modulo the modulus.
+    pub fn pow<E: AsMpz>(&self, exponent: &E) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponent)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponent: &E) -> Result<()> {
+        if is_negative(exponent) {
+            return fpowm_signed(rop, exponent, self.modulus(), |rop, exponent| {
+                self.pow_into(rop, exponent)
+            });
+        }
+        let (block_width, stretch) = (self.block_width, self.stretch);
+        let table_bitlen = block_width * stretch;
+        let bitlen = unsafe { gmp::mpz_sizeinbase(exponent.as_raw(), 2) };
+        // The bits beyond the table length extend the last part of the
+        // exponent, and are multiplied by the entry of its base alone.
+        let top = match bitlen < table_bitlen {
+            true => stretch,
+            false => bitlen - (block_width - 1) * stretch,
+        };
+        let exponent = read_limbs(exponent, bitlen.max(table_bitlen).div_ceil(LIMB_BITS));
+        let mut modulus = MontModulus::new(&self.modulus);
+        let n = modulus.len();
+        let mut acc = modulus.one();
+        for index in (0..top).rev() {
+            modulus.sqr(&mut acc);
+            let mask = match index < stretch {
+                true => (0..block_width).rev().fold(0, |mask, i| {
+                    (mask << 1) | limb_bit(&exponent, i * stretch + index)
+                }),
+                false => {
+                    limb_bit(&exponent, (block_width - 1) * stretch + index) << (block_width - 1)
+                }
+            };
+            if mask != 0 {
+                modulus.mul(&mut acc, entry(&self.table, n, mask as usize));
+            }
+        }
+        modulus.set_from_mont(rop, &acc);
+        Ok(())
+    }
+}
+
+impl From<&FpowmTable> for MontFpowmTable {
+    fn from(table: &FpowmTable) -> Self {
+        let modulus = MontModulus::new(table.modulus());
+        Self {
+            modulus: table.modulus().clone(),
+            table: table
+                .entries()
+                .iter()
+                .flat_map(|e| modulus.to_mont(e))
+                .collect(),
+            block_width: table.block_width(),
+            stretch: table.stretch(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Rng, neg, powm, product};
+
+    /// Odd moduli of one to several limbs, including ones just below a power of
+    /// the limb size, for which the final subtraction of the reduction carries.
+    fn moduli(rng: &mut Rng) -> Vec<Mpz> {
+        let mut moduli = vec![Mpz::from(3u32), Mpz::from(u64::MAX)];
+        for bits in [64, 65, 128, 256, 1000] {
+            moduli.push(rng.odd_modulus(bits));
+        }
+        let mut high = Mpz::new();
+        unsafe {
+            gmp::mpz_setbit(high.as_raw_mut(), 256);
+            gmp::mpz_sub_ui(high.as_raw_mut(), high.as_raw(), 189);
+        }
+        moduli.push(high);
+        moduli
+    }
+
+    #[test]
+    fn reduce_matches_mpz() {
+        let mut rng = Rng::new(17);
+        for modulus in moduli(&mut rng) {
+            let mut mont = MontModulus::new(&modulus);
+            let a = rng.below(&modulus);
+            let b = rng.below(&modulus);
+            let mut ap = mont.to_mont(&a);
+            let bp = mont.to_mont(&b);
+            mont.mul(&mut ap, &bp);
+            let mut expected = Mpz::new();
+            unsafe {
+                gmp::mpz_mul(expected.as_raw_mut(), a.as_raw(), b.as_raw());
+                gmp::mpz_mod(expected.as_raw_mut(), expected.as_raw(), modulus.as_raw());
+            }
+            let mut rop = Mpz::new();
+            mont.set_from_mont(&mut rop, &ap);
+            assert_eq!(rop, expected, "{modulus}");
+            mont.sqr(&mut ap);
+            mont.set_from_mont(&mut rop, &ap);
+            unsafe {
+                gmp::mpz_mul(expected.as_raw_mut(), expected.as_raw(), expected.as_raw());
+                gmp::mpz_mod(expected.as_raw_mut(), expected.as_raw(), modulus.as_raw());
+            }
+            assert_eq!(rop, expected, "{modulus}");
+            mont.set_from_mont(&mut rop, &mont.one());
+            assert_eq!(rop, Mpz::from(1u32));
+        }
+    }
+
+    #[test]
+    fn spowm_matches_table() {
+        let mut rng = Rng::new(18);
+        for modulus in moduli(&mut rng) {
+            for (len, block_width) in [(0, 1), (1, 1), (5, 2), (9, 4)] {
+                let mut bases: Vec<_> = (0..len).map(|_| rng.below(&modulus)).collect();
+                if len > 1 {
+                    bases[1] = Mpz::new();
+                }
+                let table = SpowmTable::new(&modulus, &bases, block_width).unwrap();
+                let mont = MontSpowmTable::new(&modulus, &bases, block_width).unwrap();
+                assert_eq!(mont.len(), len);
+                assert_eq!(mont.block_width(), table.block_width());
+                assert_eq!(mont.modulus(), &modulus);
+                for bits in [0, 1, 64, 300] {
+                    let exponents: Vec<_> = (0..len).map(|_| rng.bits(bits)).collect();
+                    let expected = table.pow(&exponents).unwrap();
+                    assert_eq!(mont.pow(&exponents).unwrap(), expected);
+                    assert_eq!(expected, product(&bases, &exponents, &modulus));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fpowm_matches_table() {
+        let mut rng = Rng::new(19);
+        for modulus in moduli(&mut rng) {
+            for (block_width, exponent_bitlen) in [(1, 64), (3, 100), (8, 255)] {
+                for base in [Mpz::new(), Mpz::from(1u32), rng.below(&modulus)] {
+                    let table =
+                        FpowmTable::new(&base, &modulus, block_width, exponent_bitlen).unwrap();
+                    let mont = MontFpowmTable::from(&table);
+                    assert_eq!(mont.block_width(), block_width);
+                    assert_eq!(mont.stretch(), table.stretch());
+                    assert_eq!(mont.modulus(), &modulus);
+                    // Exponents longer than the table covers extend its last
+                    // part.
+                    let covered = block_width * table.stretch();
+                    for bits in [0, 1, exponent_bitlen, covered, covered + 1, covered + 150] {
+                        let exponent = rng.bits(bits);
+                        let expected = table.pow(&exponent).unwrap();
+                        assert_eq!(mont.pow(&exponent).unwrap(), expected, "{bits}");
+                        assert_eq!(expected, powm(&base, &exponent, &modulus));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn negative_exponents() {
+        let mut rng = Rng::new(20);
+        let modulus = rng.prime(200);
+        let bases: Vec<_> = (0..6).map(|_| rng.below(&modulus)).collect();
+        let mut exponents: Vec<_> = (0..6).map(|_| rng.bits(150)).collect();
+        exponents[0] = neg(&exponents[0]);
+        exponents[3] = neg(&exponents[3]);
+        let table = SpowmTable::new(&modulus, &bases, 3).unwrap();
+        let mont = MontSpowmTable::try_from(&table).unwrap();
+        assert_eq!(
+            mont.pow(&exponents).unwrap(),
+            table.pow(&exponents).unwrap()
+        );
+        let table = FpowmTable::new(&bases[0], &modulus, 4, 150).unwrap();
+        let mont = MontFpowmTable::from(&table);
+        assert_eq!(
+            mont.pow(&exponents[0]).unwrap(),
+            table.pow(&exponents[0]).unwrap()
+        );
+        let zero = MontFpowmTable::new(&Mpz::new(), &modulus, 4, 150).unwrap();
+        assert_eq!(zero.pow(&exponents[0]), Err(Error::NotInvertible));
+    }
+
+    #[test]
+    fn even_modulus() {
+        let modulus = Mpz::from(100u32);
+        let bases = [Mpz::from(3u32)];
+        assert_eq!(
+            MontSpowmTable::new(&modulus, &bases, 1).map(|_| ()),
+            Err(Error::InvalidModulus)
+        );
+        let table = SpowmTable::new(&modulus, &bases, 1).unwrap();
+        assert_eq!(
+            MontSpowmTable::try_from(&table).map(|_| ()),
+            Err(Error::InvalidModulus)
+        );
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::error::{Error, Result};
+use gmp_mpfr_sys::gmp::{self, limb_t, mpz_ptr, mpz_srcptr, mpz_t};
+use std::cmp::Ordering;
+use std::ffi::{CString, c_char, c_int, c_long, c_ulong};
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::str::FromStr;
+
+const LIMB_BITS: usize = gmp::LIMB_BITS as usize;
+
+/// Integer types that can be handed to GMPMEE without copying.
+///
+/// A slice of an implementing type is passed to the C functions as an array
+/// of `mpz_t`.
+///
+/// # Safety
+///
+/// Implementors must have the same size and alignment as `mpz_t`, consist of a
+/// single `mpz_t`, and that `mpz_t` must always be initialized.
+pub unsafe trait AsMpz {
+    /// Returns a pointer to the inner `mpz_t`.
+    fn as_raw(&self) -> mpz_srcptr {
+        (self as *const Self).cast()
+    }
+
+    /// Returns a mutable pointer to the inner `mpz_t`.
+    fn as_raw_mut(&mut self) -> mpz_ptr {
+        (self as *mut Self).cast()
+    }
+}
+
+/// Returns a pointer to the first `mpz_t` of the slice.
+pub(crate) fn slice_as_raw<T: AsMpz>(s: &[T]) -> mpz_srcptr {
+    s.as_ptr().cast()
+}
+
+/// Returns the limbs of the absolute value of `op`, padded with zeros to `n`
+/// limbs. `op` must fit in `n` limbs.
+pub(crate) fn read_limbs<T: AsMpz>(op: &T, n: usize) -> Vec<limb_t> {
+    let mut limbs = vec![0; n];
+    unsafe {
+        let size = gmp::mpz_size(op.as_raw());
+        let src = gmp::mpz_limbs_read(op.as_raw());
+        limbs[..size].copy_from_slice(std::slice::from_raw_parts(src, size));
+    }
+    limbs
+}
+
+/// Sets `rop` to the non-negative integer with the given limbs.
+pub(crate) fn write_limbs<T: AsMpz>(rop: &mut T, limbs: &[limb_t]) {
+    unsafe {
+        let dst = gmp::mpz_limbs_write(rop.as_raw_mut(), limbs.len().max(1) as _);
+        std::ptr::copy_nonoverlapping(limbs.as_ptr(), dst, limbs.len());
+        gmp::mpz_limbs_finish(rop.as_raw_mut(), limbs.len() as _);
+    }
+}
+
+/// Returns the bit of `limbs` at the public position `index`.
+pub(crate) fn limb_bit(limbs: &[limb_t], index: usize) -> limb_t {
+    (limbs[index / LIMB_BITS] >> (index % LIMB_BITS)) & 1
+}
+
+/// Owned arbitrary precision integer, a thin wrapper around `mpz_t`.
+///
+/// It offers only what is needed to feed the safe wrappers. Use
+/// [rug](https://crates.io/crates/rug) for general purpose arithmetic.
+#[repr(transparent)]
+pub struct Mpz {
+    inner: mpz_t,
+}
+
+// `mpz_t` owns its limbs, nothing is shared between different values.
+unsafe impl Send for Mpz {}
+unsafe impl Sync for Mpz {}
+
+unsafe impl AsMpz for Mpz {}
+
+impl Mpz {
+    /// Creates an integer with value zero.
+    pub fn new() -> Self {
+        unsafe {
+            let mut z = MaybeUninit::uninit();
+            gmp::mpz_init(z.as_mut_ptr());
+            Self::from_raw(z.assume_init())
+        }
+    }
+
+    /// Creates an integer from an initialized `mpz_t`, taking ownership of it.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be initialized and must not be cleared elsewhere.
+    pub unsafe fn from_raw(raw: mpz_t) -> Self {
+        Self { inner: raw }
+    }
+
+    /// Converts the integer into a raw `mpz_t`, which must then be cleared by the caller.
+    pub fn into_raw(self) -> mpz_t {
+        let raw = self.inner;
+        std::mem::forget(self);
+        raw
+    }
+
+    /// Returns a reference to an `mpz_t` as an [`Mpz`].
+    ///
+    /// # Safety
+    ///
+    /// `raw` must point to an initialized `mpz_t` that outlives the returned reference.
+    pub unsafe fn from_raw_ref<'a>(raw: mpz_srcptr) -> &'a Self {
+        unsafe { &*raw.cast() }
+    }
+
+    /// Parses an integer in the given radix (2 to 62).
+    pub fn from_str_radix(s: &str, radix: i32) -> Result<Self> {
+        if !(2..=62).contains(&radix) {
+            return Err(Error::ParseInteger);
+        }
+        let c = CString::new(s.trim()).map_err(|_| Error::ParseInteger)?;
+        let mut z = Self::new();
+        match unsafe { gmp::mpz_set_str(z.as_raw_mut(), c.as_ptr(), radix as c_int) } {
+            0 => Ok(z),
+            _ => Err(Error::ParseInteger),
+        }
+    }
+
+    /// Formats the integer in the given radix (2 to 36).
+    pub fn to_string_radix(&self, radix: i32) -> String {
+        assert!((2..=36).contains(&radix), "radix out of range");
+        unsafe {
+            let len = gmp::mpz_sizeinbase(self.as_raw(), radix as c_int) + 2;
+            let mut buf = vec![0u8; len];
+            gmp::mpz_get_str(
+                buf.as_mut_ptr() as *mut c_char,
+                radix as c_int,
+                self.as_raw(),
+            );
+            let end = buf.iter().position(|&b| b == 0).unwrap_or(len);
+            buf.truncate(end);
+            String::from_utf8_unchecked(buf)
+        }
+    }
+
+    /// Returns the number of bits of the absolute value, zero for zero.
+    pub fn significant_bits(&self) -> usize {
+        match self.signum() {
+            0 => 0,
+            _ => unsafe { gmp::mpz_sizeinbase(self.as_raw(), 2) },
+        }
+    }
+
+    /// Returns -1, 0 or 1 depending on the sign of the integer.
+    pub fn signum(&self) -> i32 {
+        unsafe { gmp::mpz_sgn(self.as_raw()) }
+    }
+
+    /// Returns `true` if the integer is odd.
+    pub fn is_odd(&self) -> bool {
+        unsafe { gmp::mpz_odd_p(self.as_raw()) != 0 }
+    }
+}
+
+impl Default for Mpz {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Mpz {
+    fn drop(&mut self) {
+        unsafe { gmp::mpz_clear(&mut self.inner) }
+    }
+}
+
+impl Clone for Mpz {
+    fn clone(&self) -> Self {
+        unsafe {
+            let mut z = MaybeUninit::uninit();
+            gmp::mpz_init_set(z.as_mut_ptr(), self.as_raw());
+            Self::from_raw(z.assume_init())
+        }
+    }
+}
+
+impl PartialEq for Mpz {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Mpz {}
+
+impl PartialOrd for Mpz {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Mpz {
+    fn cmp(&self, other: &Self) -> Ordering {
+        unsafe { gmp::mpz_cmp(self.as_raw(), other.as_raw()) }.cmp(&0)
+    }
+}
+
+impl From<u64> for Mpz {
+    fn from(value: u64) -> Self {
+        unsafe {
+            let mut z = MaybeUninit::uninit();
+            gmp::mpz_init_set_ui(z.as_mut_ptr(), value as c_ulong);
+            Self::from_raw(z.assume_init())
+        }
+    }
+}
+
+impl From<i64> for Mpz {
+    fn from(value: i64) -> Self {
+        unsafe {
+            let mut z = MaybeUninit::uninit();
+            gmp::mpz_init_set_si(z.as_mut_ptr(), value as c_long);
+            Self::from_raw(z.assume_init())
+        }
+    }
+}
+
+impl From<u32> for Mpz {
+    fn from(value: u32) -> Self {
+        Self::from(value as u64)
+    }
+}
+
+impl From<i32> for Mpz {
+    fn from(value: i32) -> Self {
+        Self::from(value as i64)
+    }
+}
+
+impl FromStr for Mpz {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_str_radix(s, 10)
+    }
+}
+
+impl fmt::Display for Mpz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_radix(10))
+    }
+}
+
+impl fmt::Debug for Mpz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for Mpz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_radix(16))
+    }
+}
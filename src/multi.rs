@@ -0,0 +1,247 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::error::{Error, Result};
+use crate::mpz::{AsMpz, Mpz};
+use crate::signed::{has_negative, pow_signed};
+use crate::spowm::{SpowmTable, check_block_width, check_len, check_modulus};
+use gmp_mpfr_sys::gmp;
+
+/// Table for exponentiation of several fixed bases, such as the generators of
+/// Pedersen commitments.
+///
+/// Each exponent is split in `block_width` parts of `stretch =
+/// ceil(exponent_bitlen / block_width)` bits, as in [`FpowmTable`](crate::FpowmTable),
+/// and the table is a [`SpowmTable`] of block width `block_width` over the
+/// bases `b_j^(2^(i * stretch))`, one block per base. The product of the
+/// `b_j^e_j` then costs `stretch` squarings shared by all the bases, and
+/// `stretch` multiplications per base, where separate fixed base tables
+/// would square `stretch` times for each base.
+///
+/// Longer exponents are still accepted, with their extra bits in the last
+/// part, but are slower.
+#[derive(Debug, Clone)]
+pub struct MultiFpowmTable {
+    table: SpowmTable,
+    len: usize,
+    block_width: usize,
+    stretch: usize,
+}
+
+impl MultiFpowmTable {
+    /// Computes the powers of the bases and the subtables.
+    ///
+    /// The modulus must be greater than one, the block width must lie in
+    /// `1..=MAX_BLOCK_WIDTH` and the exponent bit length must not be zero.
+    pub fn new<M: AsMpz, B: AsMpz>(
+        modulus: &M,
+        bases: &[B],
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        check_modulus(modulus)?;
+        check_block_width(block_width)?;
+        if exponent_bitlen == 0 {
+            return Err(Error::InvalidExponentBitlen(exponent_bitlen));
+        }
+        let stretch = exponent_bitlen.div_ceil(block_width);
+        let mut powers = Vec::with_capacity(bases.len() * block_width);
+        for base in bases {
+            let mut power = Mpz::new();
+            unsafe { gmp::mpz_mod(power.as_raw_mut(), base.as_raw(), modulus.as_raw()) };
+            for i in 0..block_width {
+                if i > 0 {
+                    for _ in 0..stretch {
+                        unsafe {
+                            gmp::mpz_mul(power.as_raw_mut(), power.as_raw(), power.as_raw());
+                            gmp::mpz_mod(power.as_raw_mut(), power.as_raw(), modulus.as_raw());
+                        }
+                    }
+                }
+                powers.push(power.clone());
+            }
+        }
+        Ok(Self {
+            table: SpowmTable::new(modulus, &powers, block_width)?,
+            len: bases.len(),
+            block_width,
+            stretch,
+        })
+    }
+
+    /// Number of bases.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the table has no bases.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of parts each exponent is split into.
+    pub fn block_width(&self) -> usize {
+        self.block_width
+    }
+
+    /// Number of bits of each part, the number of squarings.
+    pub fn stretch(&self) -> usize {
+        self.stretch
+    }
+
+    /// Number of exponent bits covered by the parts, `block_width * stretch`.
+    pub fn exponent_bitlen(&self) -> usize {
+        self.block_width * self.stretch
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        self.table.modulus()
+    }
+
+    /// Computes the product of the `b_j^e_j` modulo the modulus.
+    ///
+    /// The number of exponents must be the number of bases. Negative exponents
+    /// are handled as in [`spowm`](crate::spowm()), with two passes over the
+    /// table.
+    pub fn pow<E: AsMpz>(&self, exponents: &[E]) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponents)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponents: &[E]) -> Result<()> {
+        check_len(self.len, exponents.len())?;
+        if has_negative(exponents) {
+            return pow_signed(rop, exponents, self.modulus(), |rop, exponents| {
+                self.pow_into(rop, exponents)
+            });
+        }
+        let block_width = self.block_width;
+        let mut parts = vec![Mpz::new(); self.len * block_width];
+        for (exponent, parts) in exponents.iter().zip(parts.chunks_mut(block_width)) {
+            for (i, part) in parts.iter_mut().enumerate() {
+                unsafe {
+                    gmp::mpz_fdiv_q_2exp(
+                        part.as_raw_mut(),
+                        exponent.as_raw(),
+                        (i * self.stretch) as _,
+                    );
+                    if i + 1 < block_width {
+                        gmp::mpz_fdiv_r_2exp(part.as_raw_mut(), part.as_raw(), self.stretch as _);
+                    }
+                }
+            }
+        }
+        self.table.pow_into(rop, &parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spowm_naive;
+    use crate::testing::{Rng, neg};
+
+    fn naive(bases: &[Mpz], exponents: &[Mpz], modulus: &Mpz) -> Mpz {
+        let mut rop = Mpz::new();
+        spowm_naive(&mut rop, bases, exponents, modulus).unwrap();
+        rop
+    }
+
+    #[test]
+    fn pow_matches_naive() {
+        let mut rng = Rng::new(29);
+        // Bit lengths that are not multiples of the block width leave fewer
+        // bits in the last part.
+        for (len, block_width, exponent_bitlen) in
+            [(1, 1, 64), (3, 4, 256), (5, 3, 100), (4, 8, 255), (2, 6, 7)]
+        {
+            let modulus = rng.odd_modulus(256);
+            let bases: Vec<_> = (0..len).map(|_| rng.below(&modulus)).collect();
+            let table =
+                MultiFpowmTable::new(&modulus, &bases, block_width, exponent_bitlen).unwrap();
+            assert_eq!(table.len(), len);
+            assert_eq!(table.block_width(), block_width);
+            assert_eq!(table.stretch(), exponent_bitlen.div_ceil(block_width));
+            assert_eq!(table.exponent_bitlen(), block_width * table.stretch());
+            assert_eq!(table.modulus(), &modulus);
+            let covered = table.exponent_bitlen();
+            for bits in [0, 1, exponent_bitlen, covered + 1, covered + 100] {
+                let exponents: Vec<_> = (0..len).map(|_| rng.bits(bits)).collect();
+                assert_eq!(
+                    table.pow(&exponents).unwrap(),
+                    naive(&bases, &exponents, &modulus),
+                    "{block_width} {exponent_bitlen} {bits}"
+                );
+            }
+            // Zero exponents next to full ones.
+            let mut exponents: Vec<_> = (0..len).map(|_| rng.bits(exponent_bitlen)).collect();
+            exponents[0] = Mpz::new();
+            assert_eq!(
+                table.pow(&exponents).unwrap(),
+                naive(&bases, &exponents, &modulus)
+            );
+        }
+    }
+
+    #[test]
+    fn mixed_signs() {
+        let mut rng = Rng::new(30);
+        let modulus = rng.prime(256);
+        let bases: Vec<_> = (0..5).map(|_| rng.below(&modulus)).collect();
+        let table = MultiFpowmTable::new(&modulus, &bases, 3, 200).unwrap();
+        let mut exponents: Vec<_> = (0..5).map(|_| rng.bits(200)).collect();
+        exponents[1] = neg(&exponents[1]);
+        exponents[4] = neg(&exponents[4]);
+        assert_eq!(
+            table.pow(&exponents).unwrap(),
+            naive(&bases, &exponents, &modulus)
+        );
+        let zero = MultiFpowmTable::new(&modulus, &[Mpz::new()], 2, 64).unwrap();
+        assert_eq!(
+            zero.pow(&[neg(&Mpz::from(3u32))]),
+            Err(Error::NotInvertible)
+        );
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let modulus = Mpz::from(101u32);
+        let bases = [Mpz::from(2u32), Mpz::from(3u32)];
+        let table = MultiFpowmTable::new(&modulus, &bases, 2, 16).unwrap();
+        assert_eq!(
+            table.pow(&bases[..1]),
+            Err(Error::LengthMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+        let empty = MultiFpowmTable::new(&modulus, &[] as &[Mpz], 2, 16).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.pow(&[] as &[Mpz]).unwrap(), Mpz::from(1u32));
+        for (block_width, bitlen, error) in [
+            (0, 16, Error::InvalidBlockWidth(0)),
+            (2, 0, Error::InvalidExponentBitlen(0)),
+        ] {
+            assert_eq!(
+                MultiFpowmTable::new(&modulus, &bases, block_width, bitlen).map(|_| ()),
+                Err(error)
+            );
+        }
+    }
+}
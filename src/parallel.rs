@@ -0,0 +1,220 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Multi-threaded exponentiation with [rayon](https://crates.io/crates/rayon),
+//! enabled by the `rayon` feature.
+//!
+//! [`spowm_block_batch`](crate::spowm_block_batch) computes its batches one
+//! after another. Here, the batches are distributed over a thread pool, each
+//! with its own [`SpowmTable`], and the partial products are combined in a
+//! reduction tree.
+
+use crate::error::{Error, Result};
+use crate::mpz::{AsMpz, Mpz};
+use crate::signed::{has_negative, spowm_signed};
+use crate::spowm::{BlockBatch, SpowmTable, check_len, check_modulus};
+use gmp_mpfr_sys::gmp;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// Sets `rop` to `rop * op` modulo `modulus`.
+fn mul_mod<M: AsMpz>(rop: &mut Mpz, op: &Mpz, modulus: &M) {
+    unsafe {
+        gmp::mpz_mul(rop.as_raw_mut(), rop.as_raw(), op.as_raw());
+        gmp::mpz_mod(rop.as_raw_mut(), rop.as_raw(), modulus.as_raw());
+    }
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` on the current
+/// rayon thread pool, writing the result into `rop`.
+///
+/// The bases are split in batches of `params.batch_len()`, and each batch is
+/// computed with a table of blocks of `params.block_width()` bases. The
+/// parameters are checked as in [`spowm`](crate::spowm()).
+pub fn par_spowm_block_batch<R, B, E, M>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+    params: BlockBatch,
+) -> Result<()>
+where
+    R: AsMpz,
+    B: AsMpz + Sync,
+    E: AsMpz + Sync,
+    M: AsMpz + Sync,
+{
+    if has_negative(exponents) {
+        return spowm_signed(rop, bases, exponents, modulus, |rop, bases, exponents| {
+            par_spowm_block_batch(rop, bases, exponents, modulus, params)
+        });
+    }
+    check_modulus(modulus)?;
+    check_len(bases.len(), exponents.len())?;
+    let product = bases
+        .par_chunks(params.batch_len())
+        .zip(exponents.par_chunks(params.batch_len()))
+        .map(|(bases, exponents)| {
+            SpowmTable::new(modulus, bases, params.block_width())?.pow(exponents)
+        })
+        .try_reduce(
+            || Mpz::from(1u32),
+            |mut a, b| {
+                mul_mod(&mut a, &b, modulus);
+                Ok(a)
+            },
+        )?;
+    unsafe { gmp::mpz_set(rop.as_raw_mut(), product.as_raw()) };
+    Ok(())
+}
+
+/// Parallel simultaneous exponentiation with a given batch length and number of
+/// threads.
+///
+/// Built with [`new`](Self::new), the computations run on the current rayon
+/// thread pool, usually the global one. Built with
+/// [`with_threads`](Self::with_threads), they run on a pool of their own.
+#[derive(Debug)]
+pub struct ParSpowm {
+    params: BlockBatch,
+    pool: Option<ThreadPool>,
+}
+
+impl ParSpowm {
+    /// Computes on the current thread pool with the given parameters.
+    pub fn new(params: BlockBatch) -> Self {
+        Self { params, pool: None }
+    }
+
+    /// Computes on a new pool of `threads` threads with the given parameters.
+    ///
+    /// Zero threads lets rayon choose, usually one per logical core.
+    pub fn with_threads(params: BlockBatch, threads: usize) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|_| Error::ThreadPool)?;
+        Ok(Self {
+            params,
+            pool: Some(pool),
+        })
+    }
+
+    /// Block width and batch length of the computations.
+    pub fn params(&self) -> BlockBatch {
+        self.params
+    }
+
+    /// Number of threads the computations run on.
+    pub fn threads(&self) -> usize {
+        match &self.pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        }
+    }
+
+    /// Computes the product of the `b_i^e_i` modulo `modulus`, see
+    /// [`par_spowm_block_batch`].
+    pub fn spowm<R, B, E, M>(
+        &self,
+        rop: &mut R,
+        bases: &[B],
+        exponents: &[E],
+        modulus: &M,
+    ) -> Result<()>
+    where
+        R: AsMpz + Send,
+        B: AsMpz + Sync,
+        E: AsMpz + Sync,
+        M: AsMpz + Sync,
+    {
+        match &self.pool {
+            Some(pool) => {
+                pool.install(|| par_spowm_block_batch(rop, bases, exponents, modulus, self.params))
+            }
+            None => par_spowm_block_batch(rop, bases, exponents, modulus, self.params),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spowm_naive;
+    use crate::testing::{Rng, neg};
+
+    fn naive(bases: &[Mpz], exponents: &[Mpz], modulus: &Mpz) -> Mpz {
+        let mut rop = Mpz::new();
+        spowm_naive(&mut rop, bases, exponents, modulus).unwrap();
+        rop
+    }
+
+    #[test]
+    fn batches_match_naive() {
+        let mut rng = Rng::new(12);
+        let modulus = rng.prime(256);
+        let bases: Vec<_> = (0..50).map(|_| rng.below(&modulus)).collect();
+        let mut exponents: Vec<_> = (0..50).map(|_| rng.bits(200)).collect();
+        // The last batch is shorter than the others, and a few exponents are
+        // negative, which inverts their bases.
+        let params = BlockBatch::new(3, 8).unwrap();
+        for signed in [false, true] {
+            if signed {
+                for e in exponents.iter_mut().step_by(7) {
+                    *e = neg(e);
+                }
+            }
+            for len in [0, 1, 7, 8, 9, 50] {
+                let expected = naive(&bases[..len], &exponents[..len], &modulus);
+                let mut rop = Mpz::from(5u32);
+                par_spowm_block_batch(&mut rop, &bases[..len], &exponents[..len], &modulus, params)
+                    .unwrap();
+                assert_eq!(rop, expected, "{len} {signed}");
+            }
+        }
+    }
+
+    #[test]
+    fn pools() {
+        let mut rng = Rng::new(13);
+        let modulus = rng.prime(128);
+        let bases: Vec<_> = (0..21).map(|_| rng.below(&modulus)).collect();
+        let mut exponents: Vec<_> = (0..21).map(|_| rng.bits(100)).collect();
+        exponents[4] = neg(&exponents[4]);
+        let expected = naive(&bases, &exponents, &modulus);
+        let params = BlockBatch::new(2, 5).unwrap();
+        let current = ParSpowm::new(params);
+        assert_eq!(current.params(), params);
+        assert_eq!(current.threads(), rayon::current_num_threads());
+        let own = ParSpowm::with_threads(params, 3).unwrap();
+        assert_eq!(own.threads(), 3);
+        for par in [current, own] {
+            let mut rop = Mpz::new();
+            par.spowm(&mut rop, &bases, &exponents, &modulus).unwrap();
+            assert_eq!(rop, expected);
+            par.spowm(&mut rop, &[] as &[Mpz], &[] as &[Mpz], &modulus)
+                .unwrap();
+            assert_eq!(rop, Mpz::from(1u32));
+            assert_eq!(
+                par.spowm(&mut rop, &bases, &exponents[1..], &modulus),
+                Err(Error::LengthMismatch {
+                    expected: 21,
+                    found: 20
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::gmpmee::{
+    gmpmee_millerrabin_next_rs, gmpmee_millerrabin_rs, gmpmee_millerrabin_safe_next_rs,
+    gmpmee_millerrabin_safe_rs,
+};
+use crate::mpz::{AsMpz, Mpz};
+use gmp_mpfr_sys::gmp::{self, randstate_ptr};
+use std::ffi::c_int;
+
+/// Random states that can be handed to the GMPMEE primality tests.
+///
+/// # Safety
+///
+/// [`as_raw_mut`](Self::as_raw_mut) must return a pointer to an initialized
+/// GMP random state, valid as long as the borrow of `self`.
+pub unsafe trait RandSource {
+    /// Returns a pointer to the GMP random state.
+    fn as_raw_mut(&mut self) -> randstate_ptr;
+}
+
+fn reps_to_c(reps: u32) -> c_int {
+    reps.min(c_int::MAX as u32) as c_int
+}
+
+/// Executes `reps` repetitions of the Miller-Rabin test on `n` with bases drawn
+/// from `rand`, and returns `false` if `n` is composite.
+///
+/// With zero repetitions only trial divisions are performed. Integers below two,
+/// negative ones included, are not prime.
+pub fn millerrabin<N: AsMpz, R: RandSource>(n: &N, reps: u32, rand: &mut R) -> bool {
+    // Below four, the C function returns the second bit of `n`, which is set
+    // in the two's complement of a negative integer.
+    if unsafe { gmp::mpz_cmp_ui(n.as_raw(), 2) } < 0 {
+        return false;
+    }
+    unsafe { gmpmee_millerrabin_rs(rand.as_raw_mut(), n.as_raw(), reps_to_c(reps)) != 0 }
+}
+
+/// Executes `reps` repetitions of the safe-primality test on `n` with bases
+/// drawn from `rand`, and returns `false` if `n` is not a safe prime.
+pub fn millerrabin_safe<N: AsMpz, R: RandSource>(n: &N, reps: u32, rand: &mut R) -> bool {
+    unsafe { gmpmee_millerrabin_safe_rs(rand.as_raw_mut(), n.as_raw(), reps_to_c(reps)) != 0 }
+}
+
+/// Returns the smallest prime larger than `n`, where primality is tested as in
+/// [`millerrabin`].
+pub fn millerrabin_next<N: AsMpz, R: RandSource>(n: &N, reps: u32, rand: &mut R) -> Mpz {
+    let mut rop = Mpz::new();
+    millerrabin_next_into(&mut rop, n, reps, rand);
+    rop
+}
+
+/// Same as [`millerrabin_next`], writing the result into `rop`.
+pub fn millerrabin_next_into<P: AsMpz, N: AsMpz, R: RandSource>(
+    rop: &mut P,
+    n: &N,
+    reps: u32,
+    rand: &mut R,
+) {
+    unsafe {
+        gmpmee_millerrabin_next_rs(
+            rop.as_raw_mut(),
+            rand.as_raw_mut(),
+            n.as_raw() as *mut _,
+            reps_to_c(reps),
+        )
+    }
+}
+
+/// Returns the smallest safe prime larger than `n`, where safe-primality is
+/// tested as in [`millerrabin_safe`].
+pub fn millerrabin_safe_next<N: AsMpz, R: RandSource>(n: &N, reps: u32, rand: &mut R) -> Mpz {
+    let mut rop = Mpz::new();
+    millerrabin_safe_next_into(&mut rop, n, reps, rand);
+    rop
+}
+
+/// Same as [`millerrabin_safe_next`], writing the result into `rop`.
+pub fn millerrabin_safe_next_into<P: AsMpz, N: AsMpz, R: RandSource>(
+    rop: &mut P,
+    n: &N,
+    reps: u32,
+    rand: &mut R,
+) {
+    unsafe {
+        gmpmee_millerrabin_safe_next_rs(
+            rop.as_raw_mut(),
+            rand.as_raw_mut(),
+            n.as_raw() as *mut _,
+            reps_to_c(reps),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Rng;
+
+    const REPS: u32 = 20;
+
+    fn mersenne(exponent: u32) -> Mpz {
+        let mut z = Mpz::new();
+        unsafe {
+            gmp::mpz_setbit(z.as_raw_mut(), exponent as _);
+            gmp::mpz_sub_ui(z.as_raw_mut(), z.as_raw(), 1);
+        }
+        z
+    }
+
+    fn product(a: &Mpz, b: &Mpz) -> Mpz {
+        let mut z = Mpz::new();
+        unsafe { gmp::mpz_mul(z.as_raw_mut(), a.as_raw(), b.as_raw()) };
+        z
+    }
+
+    #[test]
+    fn primes_and_composites() {
+        let mut rng = Rng::new(4);
+        for p in [2, 3, 5, 7, 11, 97, 7919, 65537] {
+            assert!(millerrabin(&Mpz::from(p as u32), REPS, &mut rng), "{p}");
+        }
+        for p in [mersenne(61), mersenne(127), mersenne(521)] {
+            assert!(millerrabin(&p, REPS, &mut rng), "{p}");
+        }
+        // 561 and 41041 are Carmichael numbers.
+        for n in [0, 1, 4, 9, 15, 561, 41041, 7917] {
+            assert!(!millerrabin(&Mpz::from(n as u32), REPS, &mut rng), "{n}");
+        }
+        let semiprime = product(&mersenne(61), &mersenne(89));
+        assert!(!millerrabin(&semiprime, REPS, &mut rng));
+        for n in [-1, -2, -3, -5, -7, -97] {
+            assert!(!millerrabin(&Mpz::from(n), REPS, &mut rng), "{n}");
+        }
+    }
+
+    #[test]
+    fn safe_primes() {
+        let mut rng = Rng::new(5);
+        for p in [5u32, 7, 11, 23, 47, 59, 83, 107, 2039] {
+            assert!(millerrabin_safe(&Mpz::from(p), REPS, &mut rng), "{p}");
+        }
+        // Primes that are not safe, and composites.
+        for n in [2u32, 3, 13, 17, 29, 97, 0, 1, 4, 9, 561] {
+            assert!(!millerrabin_safe(&Mpz::from(n), REPS, &mut rng), "{n}");
+        }
+        for n in [-1, -5, -7, -23] {
+            assert!(!millerrabin_safe(&Mpz::from(n), REPS, &mut rng), "{n}");
+        }
+    }
+
+    #[test]
+    fn next_primes() {
+        let mut rng = Rng::new(6);
+        for (n, next) in [(-5, 2), (0, 2), (1, 2), (2, 3), (3, 5), (4, 5), (89, 97)] {
+            assert_eq!(
+                millerrabin_next(&Mpz::from(n), REPS, &mut rng),
+                Mpz::from(next),
+                "{n}"
+            );
+        }
+        let mut rop = Mpz::from(1000u32);
+        millerrabin_next_into(&mut rop, &mersenne(61), REPS, &mut rng);
+        assert!(rop > mersenne(61));
+        assert!(millerrabin(&rop, REPS, &mut rng));
+    }
+
+    #[test]
+    fn next_safe_primes() {
+        let mut rng = Rng::new(7);
+        for (n, next) in [
+            (-3, 5),
+            (0, 5),
+            (4, 5),
+            (5, 7),
+            (6, 7),
+            (7, 11),
+            (11, 23),
+            (24, 47),
+        ] {
+            assert_eq!(
+                millerrabin_safe_next(&Mpz::from(n), REPS, &mut rng),
+                Mpz::from(next),
+                "{n}"
+            );
+        }
+        let mut rop = Mpz::new();
+        millerrabin_safe_next_into(&mut rop, &Mpz::from(1u32 << 20), REPS, &mut rng);
+        assert!(millerrabin_safe(&rop, REPS, &mut rng));
+    }
+}
@@ -0,0 +1,531 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::error::{Error, Result};
+use crate::fpowm::FpowmTable;
+use crate::mpz::{AsMpz, Mpz, limb_bit, read_limbs, write_limbs};
+use crate::spowm::{SpowmTable, check_exponents, check_len};
+use gmp_mpfr_sys::gmp::{self, limb_t};
+
+const LIMB_BITS: usize = gmp::LIMB_BITS as usize;
+
+/// Returns the limbs of `exponent`, padded to hold `max_bits` bits, or an
+/// error if the exponent is longer. The exponent must not be negative.
+///
+/// GMP only stores the `mpz_size` limbs up to the most significant non-zero
+/// one, and reading past them is out of bounds, so the copy and the length
+/// check depend on that limb count. Only the exponentiation loops that follow
+/// are constant time.
+pub(crate) fn exponent_limbs<E: AsMpz>(exponent: &E, max_bits: usize) -> Result<Vec<limb_t>> {
+    let n = max_bits.div_ceil(LIMB_BITS);
+    let too_long = || Error::ExponentTooLong {
+        bits: unsafe { gmp::mpz_sizeinbase(exponent.as_raw(), 2) },
+        max: max_bits,
+    };
+    if unsafe { gmp::mpz_size(exponent.as_raw()) } > n {
+        return Err(too_long());
+    }
+    let limbs = read_limbs(exponent, n);
+    let rem = max_bits % LIMB_BITS;
+    if rem != 0 && limbs[n - 1] >> rem != 0 {
+        return Err(too_long());
+    }
+    Ok(limbs)
+}
+
+/// Modular multiplication of residues of `n` limbs with the `mpn_sec_*`
+/// functions, whose timing and memory accesses only depend on `n`.
+pub(crate) struct SecModulus {
+    limbs: Vec<limb_t>,
+    prod: Vec<limb_t>,
+    scratch: Vec<limb_t>,
+}
+
+impl SecModulus {
+    /// The modulus must be greater than one.
+    pub(crate) fn new<M: AsMpz>(modulus: &M) -> Self {
+        let n = unsafe { gmp::mpz_size(modulus.as_raw()) };
+        let scratch = unsafe {
+            let n = n as _;
+            gmp::mpn_sec_mul_itch(n, n)
+                .max(gmp::mpn_sec_sqr_itch(n))
+                .max(gmp::mpn_sec_div_r_itch(2 * n, n))
+        };
+        Self {
+            limbs: read_limbs(modulus, n),
+            prod: vec![0; 2 * n],
+            scratch: vec![0; scratch as usize],
+        }
+    }
+
+    /// Number of limbs of the residues.
+    pub(crate) fn len(&self) -> usize {
+        self.limbs.len()
+    }
+
+    /// Returns the residue one.
+    pub(crate) fn one(&self) -> Vec<limb_t> {
+        let mut one = vec![0; self.len()];
+        one[0] = 1;
+        one
+    }
+
+    /// Returns `op` reduced modulo the modulus. Not constant time, for public
+    /// values only.
+    pub(crate) fn residue<T: AsMpz>(&self, op: &T) -> Vec<limb_t> {
+        let mut modulus = Mpz::new();
+        write_limbs(&mut modulus, &self.limbs);
+        let mut r = Mpz::new();
+        unsafe { gmp::mpz_mod(r.as_raw_mut(), op.as_raw(), modulus.as_raw()) };
+        read_limbs(&r, self.len())
+    }
+
+    /// Sets `rp` to `rp * bp` modulo the modulus.
+    pub(crate) fn mul(&mut self, rp: &mut [limb_t], bp: &[limb_t]) {
+        let n = self.len();
+        unsafe {
+            gmp::mpn_sec_mul(
+                self.prod.as_mut_ptr(),
+                rp.as_ptr(),
+                n as _,
+                bp.as_ptr(),
+                n as _,
+                self.scratch.as_mut_ptr(),
+            )
+        };
+        self.reduce(rp);
+    }
+
+    /// Sets `rp` to `rp^2` modulo the modulus.
+    pub(crate) fn sqr(&mut self, rp: &mut [limb_t]) {
+        let n = self.len();
+        unsafe {
+            gmp::mpn_sec_sqr(
+                self.prod.as_mut_ptr(),
+                rp.as_ptr(),
+                n as _,
+                self.scratch.as_mut_ptr(),
+            )
+        };
+        self.reduce(rp);
+    }
+
+    /// Sets `rp` to the entry `which` of `table`, reading every entry.
+    pub(crate) fn select(&self, rp: &mut [limb_t], table: &[limb_t], which: usize) {
+        let n = self.len();
+        unsafe {
+            gmp::mpn_sec_tabselect(
+                rp.as_mut_ptr(),
+                table.as_ptr(),
+                n as _,
+                (table.len() / n) as _,
+                which as _,
+            )
+        }
+    }
+
+    fn reduce(&mut self, rp: &mut [limb_t]) {
+        let n = self.len();
+        unsafe {
+            gmp::mpn_sec_div_r(
+                self.prod.as_mut_ptr(),
+                (2 * n) as _,
+                self.limbs.as_ptr(),
+                n as _,
+                self.scratch.as_mut_ptr(),
+            )
+        };
+        rp.copy_from_slice(&self.prod[..n]);
+    }
+}
+
+/// Constant-time table for fixed base exponentiation.
+///
+/// Holds the entries of a [`FpowmTable`] as residues of the same number of
+/// limbs. [`pow`](Self::pow) runs a fixed number of iterations given by the
+/// stretch of the table, reads every entry at each lookup with
+/// `mpn_sec_tabselect`, and multiplies with `mpn_sec_mul`, `mpn_sec_sqr` and
+/// `mpn_sec_div_r`. Its timing and memory accesses thus only depend on the
+/// table, not on the exponent, and its results equal those of
+/// [`FpowmTable::pow`].
+///
+/// The table is slower than [`FpowmTable`], and only accepts exponents of at
+/// most `block_width * stretch` bits.
+///
+/// The exponent is first copied out of its `mpz_t` into `block_width * stretch`
+/// bits of limbs. GMP stores no more limbs than the exponent needs, so this copy
+/// takes time and reads memory depending on the number of non-zero limbs of the
+/// exponent: that count leaks, although the exponentiation itself does not.
+#[derive(Debug, Clone)]
+pub struct SecFpowmTable {
+    modulus: Mpz,
+    table: Vec<limb_t>,
+    block_width: usize,
+    stretch: usize,
+}
+
+impl SecFpowmTable {
+    /// Builds a [`FpowmTable`] with the same parameters and converts it, see
+    /// [`FpowmTable::new`].
+    pub fn new<B: AsMpz, M: AsMpz>(
+        base: &B,
+        modulus: &M,
+        block_width: usize,
+        exponent_bitlen: usize,
+    ) -> Result<Self> {
+        Ok(Self::from(&FpowmTable::new(
+            base,
+            modulus,
+            block_width,
+            exponent_bitlen,
+        )?))
+    }
+
+    /// Number of parts the exponent is split into.
+    pub fn block_width(&self) -> usize {
+        self.block_width
+    }
+
+    /// Number of bits of each part of the exponent.
+    pub fn stretch(&self) -> usize {
+        self.stretch
+    }
+
+    /// Largest bit length of the exponents, `block_width * stretch`.
+    pub fn exponent_bitlen(&self) -> usize {
+        self.block_width * self.stretch
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        &self.modulus
+    }
+
+    /// Computes the base to the power `exponent` modulo the modulus in
+    /// constant time.
+    ///
+    /// The exponent must not be negative nor longer than
+    /// [`exponent_bitlen`](Self::exponent_bitlen) bits.
+    pub fn pow<E: AsMpz>(&self, exponent: &E) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponent)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponent: &E) -> Result<()> {
+        check_exponents(std::slice::from_ref(exponent))?;
+        let exponent = exponent_limbs(exponent, self.exponent_bitlen())?;
+        let mut modulus = SecModulus::new(&self.modulus);
+        let mut acc = modulus.one();
+        let mut entry = vec![0; modulus.len()];
+        for index in (0..self.stretch).rev() {
+            modulus.sqr(&mut acc);
+            let mut mask = 0;
+            for i in (0..self.block_width).rev() {
+                mask = (mask << 1) | limb_bit(&exponent, i * self.stretch + index);
+            }
+            modulus.select(&mut entry, &self.table, mask as usize);
+            modulus.mul(&mut acc, &entry);
+        }
+        write_limbs(rop, &acc);
+        Ok(())
+    }
+}
+
+impl From<&FpowmTable> for SecFpowmTable {
+    fn from(table: &FpowmTable) -> Self {
+        let modulus = SecModulus::new(table.modulus());
+        Self {
+            modulus: table.modulus().clone(),
+            table: table
+                .entries()
+                .iter()
+                .flat_map(|e| modulus.residue(e))
+                .collect(),
+            block_width: table.block_width(),
+            stretch: table.stretch(),
+        }
+    }
+}
+
+/// Constant-time table for simultaneous exponentiation.
+///
+/// Holds the subtables of a [`SpowmTable`] as residues of the same number of
+/// limbs. [`pow`](Self::pow) scans a bit length fixed by the caller rather than
+/// the bit length of the exponents, reads every entry of each subtable at each
+/// lookup with `mpn_sec_tabselect`, and multiplies with the `mpn_sec_*`
+/// functions, like [`SecFpowmTable`]. Its results equal those of
+/// [`SpowmTable::pow`].
+///
+/// As for [`SecFpowmTable`], copying each exponent out of its `mpz_t` depends
+/// on its number of non-zero limbs, which thus leaks.
+#[derive(Debug, Clone)]
+pub struct SecSpowmTable {
+    modulus: Mpz,
+    tables: Vec<Vec<limb_t>>,
+    len: usize,
+    block_width: usize,
+}
+
+impl SecSpowmTable {
+    /// Builds a [`SpowmTable`] with the same parameters and converts it, see
+    /// [`SpowmTable::new`].
+    pub fn new<M: AsMpz, B: AsMpz>(modulus: &M, bases: &[B], block_width: usize) -> Result<Self> {
+        Ok(Self::from(&SpowmTable::new(modulus, bases, block_width)?))
+    }
+
+    /// Number of bases.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the table has no bases.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of bases in each block.
+    pub fn block_width(&self) -> usize {
+        self.block_width
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        &self.modulus
+    }
+
+    /// Computes the product of the `b_i^e_i` modulo the modulus in constant
+    /// time, for exponents of at most `exponent_bitlen` bits.
+    ///
+    /// The number of exponents must be the number of bases, and the exponents
+    /// must not be negative nor longer than `exponent_bitlen` bits.
+    pub fn pow<E: AsMpz>(&self, exponents: &[E], exponent_bitlen: usize) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponents, exponent_bitlen)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(
+        &self,
+        rop: &mut R,
+        exponents: &[E],
+        exponent_bitlen: usize,
+    ) -> Result<()> {
+        check_len(self.len, exponents.len())?;
+        check_exponents(exponents)?;
+        let exponents = exponents
+            .iter()
+            .map(|e| exponent_limbs(e, exponent_bitlen))
+            .collect::<Result<Vec<_>>>()?;
+        let mut modulus = SecModulus::new(&self.modulus);
+        let mut acc = modulus.one();
+        let mut entry = vec![0; modulus.len()];
+        for index in (0..exponent_bitlen).rev() {
+            modulus.sqr(&mut acc);
+            for (table, block) in self
+                .tables
+                .iter()
+                .zip(exponents.chunks(self.block_width.max(1)))
+            {
+                let mut mask = 0;
+                for e in block.iter().rev() {
+                    mask = (mask << 1) | limb_bit(e, index);
+                }
+                modulus.select(&mut entry, table, mask as usize);
+                modulus.mul(&mut acc, &entry);
+            }
+        }
+        write_limbs(rop, &acc);
+        Ok(())
+    }
+}
+
+impl From<&SpowmTable> for SecSpowmTable {
+    fn from(table: &SpowmTable) -> Self {
+        let modulus = SecModulus::new(table.modulus());
+        Self {
+            modulus: table.modulus().clone(),
+            tables: table
+                .subtables()
+                .map(|t| t.iter().flat_map(|e| modulus.residue(e)).collect())
+                .collect(),
+            len: table.len(),
+            block_width: table.block_width(),
+        }
+    }
+}
+
+/// Largest block width chosen by [`sec_spowm`], since each lookup reads the
+/// whole subtable.
+const SEC_MAX_BLOCK_WIDTH: usize = 8;
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` in constant time,
+/// for exponents of at most `exponent_bitlen` bits, writing the result into
+/// `rop`.
+///
+/// Builds a [`SecSpowmTable`] whose block width depends only on the number of
+/// bases and on `exponent_bitlen`, and computes with
+/// [`SecSpowmTable::pow_into`]. The results equal those of [`spowm()`](crate::spowm()).
+///
+/// The number of non-zero limbs of each exponent leaks when it is copied, see
+/// [`SecSpowmTable`].
+pub fn sec_spowm<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+    exponent_bitlen: usize,
+) -> Result<()> {
+    check_len(bases.len(), exponents.len())?;
+    let block_width = sec_block_width(exponent_bitlen).min(bases.len()).max(1);
+    SecSpowmTable::new(modulus, bases, block_width)?.pow_into(rop, exponents, exponent_bitlen)
+}
+
+/// Block width minimizing the number of multiplications per block, with
+/// `2^w - w - 1` for the precomputation and one per bit of the exponents.
+fn sec_block_width(exponent_bitlen: usize) -> usize {
+    (1..=SEC_MAX_BLOCK_WIDTH)
+        .min_by_key(|&w| (1 << w) - w - 1 + exponent_bitlen / w)
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spowm_naive;
+    use crate::testing::Rng;
+
+    #[test]
+    fn fpowm_matches_table() {
+        let mut rng = Rng::new(10);
+        for (block_width, exponent_bitlen) in [(1, 64), (2, 65), (3, 128), (5, 100), (8, 256)] {
+            let modulus = rng.odd_modulus(200);
+            let base = rng.below(&modulus);
+            let table = FpowmTable::new(&base, &modulus, block_width, exponent_bitlen).unwrap();
+            let sec = SecFpowmTable::from(&table);
+            let stretch = table.stretch();
+            assert_eq!(sec.block_width(), block_width);
+            assert_eq!(sec.stretch(), stretch);
+            assert_eq!(sec.exponent_bitlen(), block_width * stretch);
+            let mut exponents = vec![Mpz::new(), Mpz::from(2u32), Mpz::from(1u32)];
+            // Exponents ending on, just before and just after the boundaries
+            // between the parts, and of the largest length.
+            for i in 1..=block_width {
+                for bits in [i * stretch - 1, i * stretch, i * stretch + 1] {
+                    if (1..=sec.exponent_bitlen()).contains(&bits) {
+                        exponents.push(rng.exact_bits(bits));
+                    }
+                }
+            }
+            let mut even = rng.exact_bits(sec.exponent_bitlen());
+            unsafe { gmp::mpz_clrbit(even.as_raw_mut(), 0) };
+            exponents.push(even);
+            for exponent in &exponents {
+                assert_eq!(sec.pow(exponent).unwrap(), table.pow(exponent).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn fpowm_rejects_exponents() {
+        let table = SecFpowmTable::new(&Mpz::from(3u32), &Mpz::from(101u32), 4, 30).unwrap();
+        assert_eq!(table.exponent_bitlen(), 32);
+        let mut exponent = Mpz::new();
+        unsafe { gmp::mpz_setbit(exponent.as_raw_mut(), 32) };
+        assert_eq!(
+            table.pow(&exponent),
+            Err(Error::ExponentTooLong { bits: 33, max: 32 })
+        );
+        assert_eq!(table.pow(&Mpz::from(-1i32)), Err(Error::NegativeExponent));
+    }
+
+    fn naive(bases: &[Mpz], exponents: &[Mpz], modulus: &Mpz) -> Mpz {
+        let mut rop = Mpz::new();
+        spowm_naive(&mut rop, bases, exponents, modulus).unwrap();
+        rop
+    }
+
+    #[test]
+    fn spowm_matches_naive() {
+        let mut rng = Rng::new(11);
+        for len in [1, 2, 5, 8, 13] {
+            for block_width in 1..=SEC_MAX_BLOCK_WIDTH {
+                let modulus = rng.odd_modulus(130);
+                let bases: Vec<_> = (0..len).map(|_| rng.below(&modulus)).collect();
+                let table = SecSpowmTable::new(&modulus, &bases, block_width).unwrap();
+                assert_eq!(table.len(), len);
+                assert_eq!(table.block_width(), block_width.min(len));
+                for exponent_bitlen in [1, 63, 64, 65, 100] {
+                    // Random exponents, exponents of exactly `exponent_bitlen`
+                    // bits, and zeros.
+                    let random: Vec<_> = (0..len).map(|_| rng.bits(exponent_bitlen)).collect();
+                    let exact: Vec<_> = (0..len).map(|_| rng.exact_bits(exponent_bitlen)).collect();
+                    let mut mixed = exact.clone();
+                    mixed[len / 2] = Mpz::new();
+                    for exponents in [random, exact, mixed, vec![Mpz::new(); len]] {
+                        assert_eq!(
+                            table.pow(&exponents, exponent_bitlen).unwrap(),
+                            naive(&bases, &exponents, &modulus)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sec_spowm_matches_naive() {
+        let mut rng = Rng::new(12);
+        for (len, exponent_bitlen) in [(1, 1), (3, 16), (10, 128), (40, 256), (20, 1000)] {
+            let modulus = rng.odd_modulus(192);
+            let bases: Vec<_> = (0..len).map(|_| rng.below(&modulus)).collect();
+            let mut exponents: Vec<_> = (0..len).map(|_| rng.exact_bits(exponent_bitlen)).collect();
+            exponents[0] = Mpz::new();
+            let mut rop = Mpz::new();
+            sec_spowm(&mut rop, &bases, &exponents, &modulus, exponent_bitlen).unwrap();
+            assert_eq!(rop, naive(&bases, &exponents, &modulus));
+        }
+        assert_eq!(sec_block_width(1), 1);
+        assert_eq!(sec_block_width(1 << 20), SEC_MAX_BLOCK_WIDTH);
+    }
+
+    #[test]
+    fn spowm_rejects_exponents() {
+        let modulus = Mpz::from(1009u32);
+        let bases = [Mpz::from(2u32), Mpz::from(3u32)];
+        let table = SecSpowmTable::new(&modulus, &bases, 2).unwrap();
+        let long = [Mpz::from(1u32), Mpz::from(1u32 << 16)];
+        let error = Error::ExponentTooLong { bits: 17, max: 16 };
+        assert_eq!(table.pow(&long, 16), Err(error.clone()));
+        let mut rop = Mpz::new();
+        assert_eq!(sec_spowm(&mut rop, &bases, &long, &modulus, 16), Err(error));
+        let negative = [Mpz::from(1u32), Mpz::from(-1i32)];
+        assert_eq!(table.pow(&negative, 16), Err(Error::NegativeExponent));
+        assert_eq!(
+            sec_spowm(&mut rop, &bases, &negative, &modulus, 16),
+            Err(Error::NegativeExponent)
+        );
+        assert_eq!(
+            table.pow(&long[..1], 16),
+            Err(Error::LengthMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Binary format of the serialized tables.
+//!
+//! A file starts with an 8-byte magic identifying the kind of table and a
+//! little-endian `u32` format version, followed by the payload, and ends with
+//! the CRC-32 (IEEE) of all the preceding bytes, little-endian. In the payload,
+//! integers are little-endian `u64`, and each `mpz_t` is its byte length as a
+//! `u64` followed by the big-endian bytes of its absolute value. Arrays of
+//! residues are stored as a fixed number of little-endian limbs each, aligned
+//! on 8 bytes from the start of the file, so that they can be used in place
+//! from a memory map on little-endian targets.
+
+use crate::error::{Error, Result};
+use crate::mpz::{AsMpz, read_limbs};
+use gmp_mpfr_sys::gmp;
+use std::ffi::c_void;
+
+/// Version of the format written by this crate.
+pub(crate) const FORMAT_VERSION: u32 = 1;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| {
+        CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+/// Writes the header and payload of a table, see [`Encoder::finish`].
+pub(crate) struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new(magic: &[u8; 8]) -> Self {
+        let mut bytes = magic.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        Self { bytes }
+    }
+
+    pub(crate) fn u64(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    pub(crate) fn mpz<T: AsMpz>(&mut self, op: &T) {
+        let len = match unsafe { gmp::mpz_sgn(op.as_raw()) } {
+            0 => 0,
+            _ => unsafe { gmp::mpz_sizeinbase(op.as_raw(), 2) }.div_ceil(8),
+        };
+        self.u64(len);
+        let start = self.bytes.len();
+        self.bytes.resize(start + len, 0);
+        if len > 0 {
+            unsafe {
+                gmp::mpz_export(
+                    self.bytes[start..].as_mut_ptr() as *mut c_void,
+                    std::ptr::null_mut(),
+                    1,
+                    1,
+                    1,
+                    0,
+                    op.as_raw(),
+                )
+            };
+        }
+    }
+
+    /// Pads with zeros to a multiple of `align` bytes from the start.
+    pub(crate) fn align(&mut self, align: usize) {
+        self.bytes
+            .resize(self.bytes.len().next_multiple_of(align), 0);
+    }
+
+    /// Writes the absolute value of `op` as `n` little-endian limbs.
+    pub(crate) fn limbs<T: AsMpz>(&mut self, op: &T, n: usize) {
+        for limb in read_limbs(op, n) {
+            self.bytes.extend_from_slice(&limb.to_le_bytes());
+        }
+    }
+
+    /// Appends the checksum and returns the bytes.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let crc = crc32(&self.bytes);
+        self.bytes.extend_from_slice(&crc.to_le_bytes());
+        self.bytes
+    }
+}
+
+/// Reads the payload of a table whose header and checksum were verified.
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Checks the magic, the version and the checksum of `bytes`.
+    pub(crate) fn new(magic: &[u8; 8], bytes: &'a [u8]) -> Result<Self> {
+        let header = magic.len() + size_of::<u32>();
+        if bytes.len() < header + size_of::<u32>() || &bytes[..magic.len()] != magic {
+            return Err(Error::InvalidFormat);
+        }
+        let (body, crc) = bytes.split_at(bytes.len() - size_of::<u32>());
+        if crc32(body).to_le_bytes() != crc {
+            return Err(Error::InvalidFormat);
+        }
+        let version = u32::from_le_bytes(body[magic.len()..header].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Ok(Self {
+            bytes: &body[header..],
+            offset: header,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::InvalidFormat);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        self.offset += len;
+        Ok(head)
+    }
+
+    /// Skips the padding to a multiple of `align` bytes from the start, which
+    /// must be zeros.
+    pub(crate) fn align(&mut self, align: usize) -> Result<()> {
+        let padding = self.take(self.offset.next_multiple_of(align) - self.offset)?;
+        match padding.iter().all(|&b| b == 0) {
+            true => Ok(()),
+            false => Err(Error::InvalidFormat),
+        }
+    }
+
+    /// Number of bytes left in the payload.
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the next `len` bytes.
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        self.take(len)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<usize> {
+        let value = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        usize::try_from(value).map_err(|_| Error::InvalidFormat)
+    }
+
+    pub(crate) fn mpz<T: AsMpz>(&mut self, rop: &mut T) -> Result<()> {
+        let len = self.u64()?;
+        let bytes = self.take(len)?;
+        unsafe {
+            gmp::mpz_import(
+                rop.as_raw_mut(),
+                len,
+                1,
+                1,
+                1,
+                0,
+                bytes.as_ptr() as *const c_void,
+            )
+        };
+        Ok(())
+    }
+
+    /// Checks that the whole payload was read.
+    pub(crate) fn finish(self) -> Result<()> {
+        match self.bytes.is_empty() {
+            true => Ok(()),
+            false => Err(Error::InvalidFormat),
+        }
+    }
+}
+
+/// Sets `rop` to the integer of `n` little-endian limbs of `limb_bytes`
+/// bytes at the start of `bytes`.
+pub(crate) fn import_limbs<T: AsMpz>(rop: &mut T, bytes: &[u8], n: usize, limb_bytes: usize) {
+    unsafe {
+        gmp::mpz_import(
+            rop.as_raw_mut(),
+            n,
+            -1,
+            limb_bytes,
+            -1,
+            0,
+            bytes.as_ptr() as *const c_void,
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: &[u8; 8] = b"GMPMEETS";
+
+    fn sealed(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(payload);
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn align_rejects_padding() {
+        // The header is 12 bytes long, so 4 bytes of padding precede the
+        // next multiple of 8.
+        let bytes = sealed(&[0, 0, 0, 0, 7]);
+        let mut decoder = Decoder::new(MAGIC, &bytes).unwrap();
+        decoder.align(8).unwrap();
+        assert_eq!(decoder.remaining(), 1);
+        for i in 0..4 {
+            let mut payload = [0; 5];
+            payload[i] = 1;
+            let bytes = sealed(&payload);
+            let mut decoder = Decoder::new(MAGIC, &bytes).unwrap();
+            assert_eq!(decoder.align(8), Err(Error::InvalidFormat));
+        }
+    }
+
+    #[test]
+    fn decoder_rejects_truncated() {
+        let bytes = sealed(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Decoder::new(MAGIC, &bytes).unwrap().u64(), Ok(1));
+        for len in 0..bytes.len() {
+            assert!(Decoder::new(MAGIC, &bytes[..len]).is_err());
+        }
+        let bytes = sealed(&[1, 0, 0]);
+        assert_eq!(
+            Decoder::new(MAGIC, &bytes).unwrap().u64(),
+            Err(Error::InvalidFormat)
+        );
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::error::Result;
+use crate::gmpmee::gmpmee_spowm;
+use crate::mont::MontModulus;
+use crate::mpz::{AsMpz, limb_bit, read_limbs, slice_as_raw};
+use crate::signed::{has_negative, spowm_signed};
+use crate::spowm::{check_len, check_modulus, max_bitlen};
+use gmp_mpfr_sys::gmp::{self, limb_t};
+
+const LIMB_BITS: usize = gmp::LIMB_BITS as usize;
+
+/// Largest number of bases for which [`spowm`](crate::spowm()) uses the
+/// interleaved windows of [`spowm2`] and [`spowm3`].
+pub const INTERLEAVED_MAX_LEN: usize = 3;
+
+/// Width of the sliding windows of an exponent of `bits` bits, minimizing the
+/// `2^(k - 1)` odd powers to precompute plus the `bits / (k + 1)` windows.
+fn window_width(bits: usize) -> usize {
+    (1..=8)
+        .min_by_key(|&k| (1 << (k - 1)) + bits / (k + 1))
+        .unwrap_or(1)
+}
+
+/// Splits the exponent of limbs `limbs` and `bits` bits in sliding windows of
+/// at most `width` bits, from the most significant one.
+///
+/// Each window is returned as the position of its lowest bit, which is set,
+/// and its odd value.
+fn sliding_windows(limbs: &[limb_t], bits: usize, width: usize) -> Vec<(usize, usize)> {
+    let mut windows = Vec::new();
+    let mut top = bits;
+    while top > 0 {
+        let high = top - 1;
+        if limb_bit(limbs, high) == 0 {
+            top = high;
+            continue;
+        }
+        let mut low = top.saturating_sub(width);
+        while limb_bit(limbs, low) == 0 {
+            low += 1;
+        }
+        let value = (low..=high)
+            .rev()
+            .fold(0, |value, i| (value << 1) | limb_bit(limbs, i) as usize);
+        windows.push((low, value));
+        top = low;
+    }
+    windows
+}
+
+/// Computes the product of the `b_i^e_i` modulo the odd `modulus` with one
+/// chain of squarings, and the sliding windows of all the exponents
+/// interleaved in it.
+fn interleaved<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+) {
+    let bitlen = max_bitlen(exponents);
+    let mut modulus = MontModulus::new(modulus);
+    // For each base, its odd powers b, b^3, ..., b^(2^width - 1) and the
+    // windows of its exponent, consumed from the most significant one.
+    let mut schedules: Vec<_> = bases
+        .iter()
+        .zip(exponents)
+        .map(|(base, exponent)| {
+            let bits = unsafe { gmp::mpz_sizeinbase(exponent.as_raw(), 2) };
+            let width = window_width(bits);
+            let mut powers = vec![modulus.to_mont(base)];
+            if width > 1 {
+                let mut square = powers[0].clone();
+                modulus.sqr(&mut square);
+                for i in 1..1 << (width - 1) {
+                    let mut power = powers[i - 1].clone();
+                    modulus.mul(&mut power, &square);
+                    powers.push(power);
+                }
+            }
+            let limbs = read_limbs(exponent, bitlen.div_ceil(LIMB_BITS));
+            let windows = sliding_windows(&limbs, bits, width);
+            (powers, windows.into_iter().peekable())
+        })
+        .collect();
+    let mut acc = modulus.one();
+    for index in (0..bitlen).rev() {
+        modulus.sqr(&mut acc);
+        for (powers, windows) in &mut schedules {
+            if let Some((_, value)) = windows.next_if(|&(low, _)| low == index) {
+                modulus.mul(&mut acc, &powers[value >> 1]);
+            }
+        }
+    }
+    modulus.set_from_mont(rop, &acc);
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` for a few bases,
+/// with interleaved windows if the modulus is odd and [`gmpmee_spowm`]
+/// otherwise.
+pub(crate) fn spowm_small<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+) -> Result<()> {
+    if has_negative(exponents) {
+        return spowm_signed(rop, bases, exponents, modulus, |rop, bases, exponents| {
+            spowm_small(rop, bases, exponents, modulus)
+        });
+    }
+    check_modulus(modulus)?;
+    check_len(bases.len(), exponents.len())?;
+    match unsafe { gmp::mpz_odd_p(modulus.as_raw()) } {
+        0 => unsafe {
+            gmpmee_spowm(
+                rop.as_raw_mut(),
+                slice_as_raw(bases),
+                slice_as_raw(exponents),
+                bases.len() as _,
+                modulus.as_raw(),
+            )
+        },
+        _ => interleaved(rop, bases, exponents, modulus),
+    }
+    Ok(())
+}
+
+/// Computes `b_0^e_0 * b_1^e_1` modulo `modulus`, writing the result into
+/// `rop`.
+///
+/// This is the product of a Pedersen commitment or of a Chaum-Pedersen
+/// verification. Each exponent is split in sliding windows of odd values,
+/// of a width depending on its length, and the windows of both exponents
+/// are multiplied in along a single chain of squarings (Shamir's trick),
+/// with Montgomery multiplications. Only the odd powers of each base are
+/// precomputed, instead of the block table of [`gmpmee_spowm`]. Unlike a
+/// signed recoding of the exponents, the windows need no inverse of the bases.
+///
+/// An even modulus falls back to [`gmpmee_spowm`]. The modulus must be greater
+/// than one, and negative exponents are handled as in [`spowm`](crate::spowm()).
+pub fn spowm2<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B; 2],
+    exponents: &[E; 2],
+    modulus: &M,
+) -> Result<()> {
+    spowm_small(rop, bases, exponents, modulus)
+}
+
+/// Computes `b_0^e_0 * b_1^e_1 * b_2^e_2` modulo `modulus`, writing the result
+/// into `rop`.
+///
+/// Same method and requirements as [`spowm2`].
+pub fn spowm3<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B; 3],
+    exponents: &[E; 3],
+    modulus: &M,
+) -> Result<()> {
+    spowm_small(rop, bases, exponents, modulus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpz::Mpz;
+    use crate::spowm;
+    use crate::testing::{Rng, neg, product};
+
+    #[test]
+    fn window_widths() {
+        assert_eq!(window_width(0), 1);
+        assert_eq!(window_width(1), 1);
+        let widths: Vec<_> = [8, 64, 256, 1024, 3072, 1 << 16]
+            .into_iter()
+            .map(window_width)
+            .collect();
+        assert!(widths.is_sorted());
+        assert!(widths[1] > 1);
+        assert_eq!(widths[5], 8);
+    }
+
+    #[test]
+    fn windows_rebuild_the_exponent() {
+        let mut rng = Rng::new(25);
+        for width in 1..=8 {
+            for bits in [1, 63, 64, 65, 500] {
+                let exponent = rng.exact_bits(bits);
+                let limbs = read_limbs(&exponent, bits.div_ceil(LIMB_BITS));
+                let windows = sliding_windows(&limbs, bits, width);
+                let mut sum = Mpz::new();
+                let mut top = bits;
+                for &(low, value) in &windows {
+                    assert!(low < top && value % 2 == 1 && value < 1 << width);
+                    assert!(value >> (top - low) == 0);
+                    top = low;
+                    let mut term = Mpz::from(value as u64);
+                    unsafe {
+                        gmp::mpz_mul_2exp(term.as_raw_mut(), term.as_raw(), low as _);
+                        gmp::mpz_add(sum.as_raw_mut(), sum.as_raw(), term.as_raw());
+                    }
+                }
+                assert_eq!(sum, exponent, "{width} {bits}");
+            }
+        }
+    }
+
+    #[test]
+    fn unequal_exponents() {
+        let mut rng = Rng::new(26);
+        let modulus = rng.odd_modulus(512);
+        let bases = [
+            rng.below(&modulus),
+            rng.below(&modulus),
+            rng.below(&modulus),
+        ];
+        let (zero, long, short) = (Mpz::new(), rng.exact_bits(3000), rng.exact_bits(10));
+        let mut rop = Mpz::new();
+        for exponents in [
+            [zero.clone(), long.clone()],
+            [long.clone(), zero.clone()],
+            [short.clone(), long.clone()],
+            [zero.clone(), zero.clone()],
+        ] {
+            spowm2(
+                &mut rop,
+                bases[..2].try_into().unwrap(),
+                &exponents,
+                &modulus,
+            )
+            .unwrap();
+            assert_eq!(rop, product(&bases[..2], &exponents, &modulus));
+        }
+        let exponents = [zero, long, short];
+        spowm3(&mut rop, &bases, &exponents, &modulus).unwrap();
+        assert_eq!(rop, product(&bases, &exponents, &modulus));
+    }
+
+    #[test]
+    fn even_modulus_falls_back() {
+        let mut rng = Rng::new(27);
+        let mut modulus = rng.exact_bits(300);
+        unsafe { gmp::mpz_clrbit(modulus.as_raw_mut(), 0) };
+        let bases = [
+            rng.below(&modulus),
+            rng.below(&modulus),
+            rng.below(&modulus),
+        ];
+        let exponents = [rng.bits(200), Mpz::new(), rng.bits(1000)];
+        let mut rop = Mpz::new();
+        spowm3(&mut rop, &bases, &exponents, &modulus).unwrap();
+        assert_eq!(rop, product(&bases, &exponents, &modulus));
+        spowm_small(&mut rop, &bases[..2], &exponents[..2], &modulus).unwrap();
+        assert_eq!(rop, product(&bases[..2], &exponents[..2], &modulus));
+    }
+
+    #[test]
+    fn spowm_dispatches_few_bases() {
+        let mut rng = Rng::new(28);
+        let mut even = rng.exact_bits(256);
+        unsafe { gmp::mpz_clrbit(even.as_raw_mut(), 0) };
+        // Negative exponents need invertible bases, hence the prime modulus.
+        for (modulus, signed) in [
+            (rng.odd_modulus(256), false),
+            (rng.prime(256), true),
+            (even, false),
+        ] {
+            for len in 2..=INTERLEAVED_MAX_LEN {
+                let bases: Vec<_> = (0..len).map(|_| rng.below(&modulus)).collect();
+                let mut exponents: Vec<_> = (0..len).map(|_| rng.bits(300)).collect();
+                if signed {
+                    exponents[0] = neg(&exponents[0]);
+                }
+                let expected = product(&bases, &exponents, &modulus);
+                let (mut small, mut rop) = (Mpz::new(), Mpz::new());
+                spowm_small(&mut small, &bases, &exponents, &modulus).unwrap();
+                spowm(&mut rop, &bases, &exponents, &modulus).unwrap();
+                assert_eq!(small, expected);
+                assert_eq!(rop, expected);
+            }
+        }
+    }
+}
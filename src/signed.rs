@@ -0,0 +1,272 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Signed exponents for the safe wrappers.
+//!
+//! The C functions read the exponents with `mpz_sizeinbase` and `mpz_tstbit`,
+//! which see the magnitude or the two's complement of a negative integer. The
+//! product is therefore split in the numerator of the non-negative exponents
+//! and the denominator of the absolute values of the negative ones, each
+//! computed by the unsigned function, and the denominator is inverted once.
+
+use crate::error::{Error, Result};
+use crate::mpz::{AsMpz, Mpz};
+use crate::spowm::{check_len, check_modulus};
+use gmp_mpfr_sys::gmp::{self, mpz_t};
+use std::marker::PhantomData;
+
+/// Read-only view of an integer, sharing its limbs, possibly with another sign
+/// or as zero. It is never cleared.
+#[repr(transparent)]
+pub(crate) struct View<'a> {
+    raw: mpz_t,
+    _marker: PhantomData<&'a Mpz>,
+}
+
+// The view is only read, by the C functions taking `mpz_srcptr`.
+unsafe impl AsMpz for View<'_> {}
+unsafe impl Send for View<'_> {}
+unsafe impl Sync for View<'_> {}
+
+impl<'a> View<'a> {
+    /// Views `op` unchanged.
+    fn new<T: AsMpz>(op: &'a T) -> Self {
+        Self {
+            raw: unsafe { *op.as_raw() },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Views the absolute value of `op`.
+    fn abs<T: AsMpz>(op: &'a T) -> Self {
+        let mut view = Self::new(op);
+        view.raw.size = view.raw.size.abs();
+        view
+    }
+
+    /// Views `op`, or zero if `keep` is false.
+    fn keep<T: AsMpz>(op: &'a T, keep: bool) -> Self {
+        let mut view = Self::abs(op);
+        if !keep {
+            view.raw.size = 0;
+        }
+        view
+    }
+}
+
+pub(crate) fn is_negative<E: AsMpz>(exponent: &E) -> bool {
+    unsafe { gmp::mpz_sgn(exponent.as_raw()) < 0 }
+}
+
+pub(crate) fn has_negative<E: AsMpz>(exponents: &[E]) -> bool {
+    exponents.iter().any(is_negative)
+}
+
+/// Sets `rop` to `numerator / denominator` modulo `modulus`, or returns
+/// [`Error::NotInvertible`].
+fn divide<R: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    numerator: &Mpz,
+    denominator: &mut Mpz,
+    modulus: &M,
+) -> Result<()> {
+    unsafe {
+        if gmp::mpz_invert(
+            denominator.as_raw_mut(),
+            denominator.as_raw(),
+            modulus.as_raw(),
+        ) == 0
+        {
+            return Err(Error::NotInvertible);
+        }
+        gmp::mpz_mul(rop.as_raw_mut(), numerator.as_raw(), denominator.as_raw());
+        gmp::mpz_mod(rop.as_raw_mut(), rop.as_raw(), modulus.as_raw());
+    }
+    Ok(())
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` with signed
+/// exponents, `spowm` computing the products of the bases with non-negative
+/// exponents and of the other bases with the absolute values of theirs. The
+/// bases keep their signs.
+pub(crate) fn spowm_signed<R, B, E, M, F>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+    mut spowm: F,
+) -> Result<()>
+where
+    R: AsMpz,
+    B: AsMpz,
+    E: AsMpz,
+    M: AsMpz,
+    F: FnMut(&mut Mpz, &[View<'_>], &[View<'_>]) -> Result<()>,
+{
+    check_modulus(modulus)?;
+    check_len(bases.len(), exponents.len())?;
+    let mut parts: [(Vec<View<'_>>, Vec<View<'_>>); 2] = Default::default();
+    for (base, exponent) in bases.iter().zip(exponents) {
+        let (bases, exponents) = &mut parts[is_negative(exponent) as usize];
+        bases.push(View::new(base));
+        exponents.push(View::abs(exponent));
+    }
+    let [(bases, exponents), (inverted, negated)] = parts;
+    let (mut numerator, mut denominator) = (Mpz::from(1u32), Mpz::from(1u32));
+    if !bases.is_empty() {
+        spowm(&mut numerator, &bases, &exponents)?;
+    }
+    spowm(&mut denominator, &inverted, &negated)?;
+    divide(rop, &numerator, &mut denominator, modulus)
+}
+
+/// Same as [`spowm_signed`] for a table of fixed bases, `pow` computing the
+/// product with the non-negative exponents set to zero, and then with the
+/// negative ones set to zero and the others negated.
+pub(crate) fn pow_signed<R, E, M, F>(
+    rop: &mut R,
+    exponents: &[E],
+    modulus: &M,
+    mut pow: F,
+) -> Result<()>
+where
+    R: AsMpz,
+    E: AsMpz,
+    M: AsMpz,
+    F: FnMut(&mut Mpz, &[View<'_>]) -> Result<()>,
+{
+    let split = |negative: bool| -> Vec<View<'_>> {
+        exponents
+            .iter()
+            .map(|e| View::keep(e, is_negative(e) == negative))
+            .collect()
+    };
+    let (mut numerator, mut denominator) = (Mpz::new(), Mpz::new());
+    pow(&mut numerator, &split(false))?;
+    pow(&mut denominator, &split(true))?;
+    divide(rop, &numerator, &mut denominator, modulus)
+}
+
+/// Same as [`spowm_signed`] for a fixed base, `pow` computing the power with
+/// the absolute value of the negative `exponent`, which is then inverted.
+pub(crate) fn fpowm_signed<R, E, M, F>(rop: &mut R, exponent: &E, modulus: &M, pow: F) -> Result<()>
+where
+    R: AsMpz,
+    E: AsMpz,
+    M: AsMpz,
+    F: FnOnce(&mut Mpz, &View<'_>) -> Result<()>,
+{
+    let mut denominator = Mpz::new();
+    pow(&mut denominator, &View::abs(exponent))?;
+    divide(rop, &Mpz::from(1u32), &mut denominator, modulus)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mpz::Mpz;
+    use crate::testing::{Rng, neg, product};
+    use crate::{
+        BUCKET_THRESHOLD, BlockBatch, SpowmTable, spowm, spowm_block_batch, spowm_bucket,
+        spowm_naive, spowm2, spowm3,
+    };
+
+    /// Bases with every other one negated, and exponents with the given signs.
+    fn inputs(rng: &mut Rng, modulus: &Mpz, signs: &[bool]) -> (Vec<Mpz>, Vec<Mpz>) {
+        let bases = (0..signs.len())
+            .map(|i| match i % 2 {
+                0 => neg(&rng.below(modulus)),
+                _ => rng.below(modulus),
+            })
+            .collect();
+        let exponents = signs
+            .iter()
+            .map(|&negative| match negative {
+                true => neg(&rng.bits(100)),
+                false => rng.bits(100),
+            })
+            .collect();
+        (bases, exponents)
+    }
+
+    #[test]
+    fn negative_bases_keep_their_signs() {
+        let modulus = Mpz::from(1009u32);
+        let bases = [Mpz::from(-2i32), Mpz::from(3u32)];
+        let exponents = [Mpz::from(3u32), Mpz::from(-1i32)];
+        // (-2)^3 * 3^-1 = -8 * 673 modulo 1009.
+        let expected = Mpz::from(673u32 * 1001 % 1009);
+        let mut rop = Mpz::new();
+        spowm_naive(&mut rop, &bases, &exponents, &modulus).unwrap();
+        assert_eq!(rop, expected);
+        spowm(&mut rop, &bases, &exponents, &modulus).unwrap();
+        assert_eq!(rop, expected);
+    }
+
+    #[test]
+    fn signed_matches_powm() {
+        let mut rng = Rng::new(25);
+        let modulus = rng.prime(192);
+        let patterns: [&[bool]; 6] = [
+            &[true],
+            &[false, true],
+            &[true, true],
+            &[true, false, true],
+            &[true, true, true, true, true],
+            &[false, true, false, false, true, true, false],
+        ];
+        for signs in patterns {
+            let (bases, exponents) = inputs(&mut rng, &modulus, signs);
+            let expected = product(&bases, &exponents, &modulus);
+            let mut rop = Mpz::new();
+            spowm_naive(&mut rop, &bases, &exponents, &modulus).unwrap();
+            assert_eq!(rop, expected);
+            spowm(&mut rop, &bases, &exponents, &modulus).unwrap();
+            assert_eq!(rop, expected);
+            let params = BlockBatch::new(2, 3).unwrap();
+            spowm_block_batch(&mut rop, &bases, &exponents, &modulus, params).unwrap();
+            assert_eq!(rop, expected);
+            spowm_bucket(&mut rop, &bases, &exponents, &modulus, 3).unwrap();
+            assert_eq!(rop, expected);
+            let table = SpowmTable::new(&modulus, &bases, 2).unwrap();
+            assert_eq!(table.pow(&exponents).unwrap(), expected);
+            match (&bases[..], &exponents[..]) {
+                ([b0, b1], [e0, e1]) => {
+                    let (bases, exponents) = ([b0.clone(), b1.clone()], [e0.clone(), e1.clone()]);
+                    spowm2(&mut rop, &bases, &exponents, &modulus).unwrap();
+                    assert_eq!(rop, expected);
+                }
+                ([b0, b1, b2], [e0, e1, e2]) => {
+                    let bases = [b0.clone(), b1.clone(), b2.clone()];
+                    let exponents = [e0.clone(), e1.clone(), e2.clone()];
+                    spowm3(&mut rop, &bases, &exponents, &modulus).unwrap();
+                    assert_eq!(rop, expected);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn signed_bucket_dispatch_matches_powm() {
+        let mut rng = Rng::new(26);
+        let modulus = rng.prime(128);
+        let signs: Vec<bool> = (0..BUCKET_THRESHOLD).map(|i| i % 3 == 0).collect();
+        let (bases, exponents) = inputs(&mut rng, &modulus, &signs);
+        let mut rop = Mpz::new();
+        spowm(&mut rop, &bases, &exponents, &modulus).unwrap();
+        assert_eq!(rop, product(&bases, &exponents, &modulus));
+    }
+}
@@ -0,0 +1,951 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::bucket::{BUCKET_THRESHOLD, bucket_is_cheaper, bucket_window, spowm_bucket};
+use crate::error::{Error, Result};
+use crate::gmpmee::{
+    gmpmee_spowm, gmpmee_spowm_block_batch, gmpmee_spowm_clear, gmpmee_spowm_init,
+    gmpmee_spowm_naive, gmpmee_spowm_precomp, gmpmee_spowm_tab, gmpmee_spowm_table,
+};
+use crate::mpz::{AsMpz, Mpz, slice_as_raw};
+use crate::serial::{Decoder, Encoder, import_limbs};
+use crate::shamir::{INTERLEAVED_MAX_LEN, spowm_small};
+use crate::signed::{has_negative, pow_signed, spowm_signed};
+use crate::tune::{spowm_block_width, tuning_profile};
+use gmp_mpfr_sys::gmp::{self, limb_t, mpz_t};
+use std::io::{Read, Write};
+use std::mem::{MaybeUninit, size_of};
+
+const MAGIC: &[u8; 8] = b"GMPMEESP";
+
+/// Largest block width accepted by the safe wrappers.
+///
+/// Each subtable holds `2^block_width` integers.
+pub const MAX_BLOCK_WIDTH: usize = 20;
+
+pub(crate) fn check_modulus<M: AsMpz>(modulus: &M) -> Result<()> {
+    match unsafe { gmp::mpz_cmp_ui(modulus.as_raw(), 1) } > 0 {
+        true => Ok(()),
+        false => Err(Error::InvalidModulus),
+    }
+}
+
+pub(crate) fn check_block_width(block_width: usize) -> Result<()> {
+    match block_width {
+        1..=MAX_BLOCK_WIDTH => Ok(()),
+        _ => Err(Error::InvalidBlockWidth(block_width)),
+    }
+}
+
+pub(crate) fn check_len(expected: usize, found: usize) -> Result<()> {
+    match expected == found {
+        true => Ok(()),
+        false => Err(Error::LengthMismatch { expected, found }),
+    }
+}
+
+/// Largest bit length among the exponents, at least one for a non-empty slice
+/// since `mpz_sizeinbase` counts one bit for zero, as in the C functions.
+pub(crate) fn max_bitlen<E: AsMpz>(exponents: &[E]) -> usize {
+    exponents
+        .iter()
+        .map(|e| unsafe { gmp::mpz_sizeinbase(e.as_raw(), 2) })
+        .max()
+        .unwrap_or(0)
+}
+
+pub(crate) fn check_exponents<E: AsMpz>(exponents: &[E]) -> Result<()> {
+    match exponents
+        .iter()
+        .any(|e| unsafe { gmp::mpz_sgn(e.as_raw()) } < 0)
+    {
+        true => Err(Error::NegativeExponent),
+        false => Ok(()),
+    }
+}
+
+/// Returns the width of each block of the table, the last one may be narrower.
+pub(crate) fn block_widths(raw: &gmpmee_spowm_tab) -> impl Iterator<Item = usize> + use<> {
+    widths(
+        raw.len as usize,
+        raw.block_width as usize,
+        raw.tabs_len as usize,
+    )
+}
+
+fn widths(len: usize, block_width: usize, tabs_len: usize) -> impl Iterator<Item = usize> {
+    (0..tabs_len).map(move |i| match i + 1 == tabs_len {
+        true => len - i * block_width,
+        false => block_width,
+    })
+}
+
+/// Returns the subtables of the table, each holding `2^width` integers.
+pub(crate) fn subtables(raw: &gmpmee_spowm_tab) -> impl Iterator<Item = &[Mpz]> {
+    block_widths(raw)
+        .enumerate()
+        .map(|(i, w)| unsafe { std::slice::from_raw_parts(*raw.tabs.add(i) as *const Mpz, 1 << w) })
+}
+
+/// Returns the subtables of the table for writing.
+pub(crate) fn subtables_mut(raw: &mut gmpmee_spowm_tab) -> impl Iterator<Item = &mut [Mpz]> {
+    let tabs = raw.tabs;
+    block_widths(raw).enumerate().map(move |(i, w)| unsafe {
+        std::slice::from_raw_parts_mut(*tabs.add(i) as *mut Mpz, 1 << w)
+    })
+}
+
+/// Allocates a table with the same parameters as `src` and copies all its entries.
+///
+/// # Safety
+///
+/// `src` must be an initialized table.
+pub(crate) unsafe fn clone_raw(src: &gmpmee_spowm_tab) -> gmpmee_spowm_tab {
+    unsafe {
+        let mut raw = MaybeUninit::uninit();
+        // An empty table has block width zero, which the C code cannot divide by.
+        gmpmee_spowm_init(
+            raw.as_mut_ptr(),
+            src.len as usize,
+            &src.modulus,
+            (src.block_width as usize).max(1),
+        );
+        let mut raw = raw.assume_init();
+        for (dst, src) in subtables_mut(&mut raw).zip(subtables(src)) {
+            for (d, s) in dst.iter_mut().zip(src) {
+                gmp::mpz_set(d.as_raw_mut(), s.as_raw());
+            }
+        }
+        raw
+    }
+}
+
+/// Fills the subtables of `raw` with the products of `bases`, with the same
+/// values as [`gmpmee_spowm_precomp`].
+///
+/// The subtables are filled concurrently, and within a subtable the products
+/// involving base `k` of the block are computed concurrently once those of the
+/// previous bases are known.
+#[cfg(feature = "rayon")]
+pub(crate) fn par_precomp<B: AsMpz + Sync>(raw: &mut gmpmee_spowm_tab, bases: &[B]) {
+    use rayon::prelude::*;
+
+    let block_width = (raw.block_width as usize).max(1);
+    let modulus = unsafe { Mpz::from_raw_ref(&raw.modulus) }.clone();
+    let tabs: Vec<_> = subtables_mut(raw).collect();
+    tabs.into_par_iter()
+        .zip(bases.par_chunks(block_width))
+        .for_each(|(tab, bases)| unsafe {
+            gmp::mpz_set_ui(tab[0].as_raw_mut(), 1);
+            for (k, base) in bases.iter().enumerate() {
+                let (lower, upper) = tab[..2 << k].split_at_mut(1 << k);
+                let (single, products) = upper.split_first_mut().unwrap();
+                gmp::mpz_mod(single.as_raw_mut(), base.as_raw(), modulus.as_raw());
+                let single = &*single;
+                products
+                    .par_iter_mut()
+                    .zip(&lower[1..])
+                    .for_each(|(product, other)| {
+                        gmp::mpz_mul(product.as_raw_mut(), other.as_raw(), single.as_raw());
+                        gmp::mpz_mod(product.as_raw_mut(), product.as_raw(), modulus.as_raw());
+                    });
+            }
+        });
+}
+
+/// Parameters of a serialized table, stored before its entries.
+struct Layout {
+    len: usize,
+    block_width: usize,
+    entries: usize,
+    limb_bytes: usize,
+    limbs: usize,
+    modulus: Mpz,
+}
+
+impl Layout {
+    /// Reads the parameters, up to the start of the entries, and checks them
+    /// against each other, against `modulus` and against the number of bytes
+    /// left for the entries, which must end the payload.
+    fn decode<M: AsMpz>(decoder: &mut Decoder<'_>, modulus: &M) -> Result<Self> {
+        let limb_bits = decoder.u64()?;
+        let len = decoder.u64()?;
+        let block_width = decoder.u64()?;
+        let tabs_len = decoder.u64()?;
+        let mut stored = Mpz::new();
+        decoder.mpz(&mut stored)?;
+        let limbs = decoder.u64()?;
+        decoder.align(8)?;
+        if unsafe { gmp::mpz_cmp(stored.as_raw(), modulus.as_raw()) } != 0 {
+            return Err(Error::ParameterMismatch);
+        }
+        let valid = matches!(limb_bits, 32 | 64)
+            && limbs == stored.significant_bits().div_ceil(limb_bits)
+            && block_width <= MAX_BLOCK_WIDTH
+            && match block_width {
+                0 => len == 0 && tabs_len == 0,
+                _ => block_width <= len && tabs_len == len.div_ceil(block_width),
+            };
+        if !valid {
+            return Err(Error::InvalidFormat);
+        }
+        // The entries are counted without iterating over the blocks, whose
+        // number is read from the data, and must fit in the bytes left.
+        let limb_bytes = limb_bits / 8;
+        let entries = entry_count(len, block_width).ok_or(Error::InvalidFormat)?;
+        let bytes = entries
+            .checked_mul(limbs)
+            .and_then(|n| n.checked_mul(limb_bytes))
+            .ok_or(Error::InvalidFormat)?;
+        if bytes != decoder.remaining() {
+            return Err(Error::InvalidFormat);
+        }
+        Ok(Self {
+            len,
+            block_width,
+            entries,
+            limb_bytes,
+            limbs,
+            modulus: stored,
+        })
+    }
+
+    /// Reads the entries, which end the payload.
+    fn entry_bytes<'a>(&self, mut decoder: Decoder<'a>) -> Result<&'a [u8]> {
+        let bytes = decoder.bytes(self.entries * self.limbs * self.limb_bytes)?;
+        decoder.finish()?;
+        Ok(bytes)
+    }
+}
+
+/// Number of entries of the subtables of `len` bases in blocks of
+/// `block_width`, `2^block_width` per full block and `2^(len % block_width)`
+/// for a last partial one, or `None` on overflow.
+fn entry_count(len: usize, block_width: usize) -> Option<usize> {
+    if block_width == 0 {
+        return Some(0);
+    }
+    let partial = match len % block_width {
+        0 => 0,
+        rem => 1 << rem,
+    };
+    (len / block_width)
+        .checked_mul(1 << block_width)?
+        .checked_add(partial)
+}
+
+/// Memory map backing the entries of a table loaded with
+/// [`SpowmTable::map_file`].
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+struct Mapping {
+    _map: memmap2::Mmap,
+    _entries: Vec<mpz_t>,
+    _tabs: Vec<*mut mpz_t>,
+}
+
+/// Table of precomputed products of the bases, for simultaneous exponentiation
+/// with many exponent vectors.
+///
+/// Owns a [`gmpmee_spowm_tab`], filled with [`gmpmee_spowm_precomp`] on creation
+/// and cleared with [`gmpmee_spowm_clear`] on drop. Cloning copies every entry
+/// of the table.
+///
+/// The table is `Sync`, so that threads computing products of the same bases
+/// can share it, and so are the tables built on it.
+#[derive(Debug)]
+pub struct SpowmTable {
+    raw: gmpmee_spowm_tab,
+    #[cfg(feature = "mmap")]
+    mapping: Option<Mapping>,
+}
+
+// The table owns all its integers, or the mapping they live in, and is only
+// read after the precomputation: `gmpmee_spowm_table` does not modify it, and
+// the entries of a mapped table are read-only views of the mapping, which is
+// never written through them.
+unsafe impl Send for SpowmTable {}
+unsafe impl Sync for SpowmTable {}
+
+impl SpowmTable {
+    /// Allocates a table for the given modulus and bases, with subtables
+    /// for blocks of `block_width` bases, and performs the precomputation.
+    ///
+    /// The modulus must be greater than one and the block width must lie in
+    /// `1..=MAX_BLOCK_WIDTH`.
+    pub fn new<M: AsMpz, B: AsMpz>(modulus: &M, bases: &[B], block_width: usize) -> Result<Self> {
+        check_modulus(modulus)?;
+        check_block_width(block_width)?;
+        unsafe {
+            let mut raw = MaybeUninit::uninit();
+            gmpmee_spowm_init(raw.as_mut_ptr(), bases.len(), modulus.as_raw(), block_width);
+            let mut raw = raw.assume_init();
+            gmpmee_spowm_precomp(&mut raw, slice_as_raw(bases));
+            Ok(Self::owned(raw))
+        }
+    }
+
+    fn owned(raw: gmpmee_spowm_tab) -> Self {
+        Self {
+            raw,
+            #[cfg(feature = "mmap")]
+            mapping: None,
+        }
+    }
+
+    /// Same as [`new`](Self::new), with the precomputation run on the current
+    /// rayon thread pool. The table is identical.
+    #[cfg(feature = "rayon")]
+    pub fn par_new<M: AsMpz, B: AsMpz + Sync>(
+        modulus: &M,
+        bases: &[B],
+        block_width: usize,
+    ) -> Result<Self> {
+        check_modulus(modulus)?;
+        check_block_width(block_width)?;
+        unsafe {
+            let mut raw = MaybeUninit::uninit();
+            gmpmee_spowm_init(raw.as_mut_ptr(), bases.len(), modulus.as_raw(), block_width);
+            let mut raw = raw.assume_init();
+            par_precomp(&mut raw, bases);
+            Ok(Self::owned(raw))
+        }
+    }
+
+    /// Number of bases.
+    pub fn len(&self) -> usize {
+        self.raw.len as usize
+    }
+
+    /// Returns `true` if the table has no bases.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bases in each block.
+    pub fn block_width(&self) -> usize {
+        self.raw.block_width as usize
+    }
+
+    /// Modulus used in computations.
+    pub fn modulus(&self) -> &Mpz {
+        unsafe { Mpz::from_raw_ref(&self.raw.modulus) }
+    }
+
+    /// Subtables of the products of each block of bases, indexed by the bits
+    /// of the exponents of the block.
+    pub(crate) fn subtables(&self) -> impl Iterator<Item = &[Mpz]> {
+        subtables(&self.raw)
+    }
+
+    /// Pointer to the underlying table, valid as long as `self`.
+    pub fn as_raw(&self) -> *const gmpmee_spowm_tab {
+        &self.raw
+    }
+
+    /// Computes the product of the `b_i^e_i` modulo the modulus.
+    ///
+    /// The number of exponents must be the number of bases. Negative exponents
+    /// are handled as in [`spowm()`], with two passes over the table.
+    pub fn pow<E: AsMpz>(&self, exponents: &[E]) -> Result<Mpz> {
+        let mut rop = Mpz::new();
+        self.pow_into(&mut rop, exponents)?;
+        Ok(rop)
+    }
+
+    /// Same as [`pow`](Self::pow), writing the result into `rop`.
+    pub fn pow_into<R: AsMpz, E: AsMpz>(&self, rop: &mut R, exponents: &[E]) -> Result<()> {
+        check_len(self.len(), exponents.len())?;
+        if has_negative(exponents) {
+            return pow_signed(rop, exponents, self.modulus(), |rop, exponents| {
+                self.pow_into(rop, exponents)
+            });
+        }
+        unsafe {
+            gmpmee_spowm_table(rop.as_raw_mut(), &self.raw, slice_as_raw(exponents));
+        }
+        Ok(())
+    }
+}
+
+impl SpowmTable {
+    /// Serializes the table: number of bases, block width, number of blocks,
+    /// modulus and every entry of every subtable, in a versioned and
+    /// checksummed binary format.
+    ///
+    /// The entries are stored as residues of the size of the modulus, so that
+    /// [`map_file`](Self::map_file) can use them in place.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let limbs = unsafe { gmp::mpz_size(&self.raw.modulus) };
+        let mut encoder = Encoder::new(MAGIC);
+        encoder.u64(gmp::LIMB_BITS as usize);
+        encoder.u64(self.len());
+        encoder.u64(self.block_width());
+        encoder.u64(self.raw.tabs_len as usize);
+        encoder.mpz(self.modulus());
+        encoder.u64(limbs);
+        encoder.align(8);
+        for entry in subtables(&self.raw).flatten() {
+            encoder.limbs(entry, limbs);
+        }
+        encoder.finish()
+    }
+
+    /// Writes the serialized table to `writer`, see [`to_bytes`](Self::to_bytes).
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Loads a table serialized with [`to_bytes`](Self::to_bytes), without
+    /// recomputing it.
+    ///
+    /// Data that is truncated, corrupt or of another format version is
+    /// rejected, and so is a table built for another modulus. The number of
+    /// bases and the block width are those of the stored table.
+    pub fn from_bytes<M: AsMpz>(bytes: &[u8], modulus: &M) -> Result<Self> {
+        check_modulus(modulus)?;
+        let mut decoder = Decoder::new(MAGIC, bytes)?;
+        let layout = Layout::decode(&mut decoder, modulus)?;
+        let entries = layout.entry_bytes(decoder)?;
+        let mut table = unsafe {
+            let mut raw = MaybeUninit::uninit();
+            gmpmee_spowm_init(
+                raw.as_mut_ptr(),
+                layout.len,
+                modulus.as_raw(),
+                layout.block_width.max(1),
+            );
+            Self::owned(raw.assume_init())
+        };
+        let entry_len = layout.limbs * layout.limb_bytes;
+        for (entry, bytes) in subtables_mut(&mut table.raw)
+            .flatten()
+            .zip(entries.chunks(entry_len))
+        {
+            import_limbs(entry, bytes, layout.limbs, layout.limb_bytes);
+            if *entry >= layout.modulus {
+                return Err(Error::InvalidFormat);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Reads a serialized table from `reader` to its end, see
+    /// [`from_bytes`](Self::from_bytes).
+    pub fn read_from<R: Read, M: AsMpz>(mut reader: R, modulus: &M) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes, modulus)
+    }
+
+    /// Maps a file written with [`write_to`](Self::write_to) read-only into
+    /// memory and uses its entries in place, so that processes mapping the
+    /// same file share its pages.
+    ///
+    /// The file is checked as in [`from_bytes`](Self::from_bytes). The entries
+    /// can only be used in place if the file was written on a target with
+    /// the same limb size and the current target is little-endian; otherwise
+    /// [`Error::IncompatibleLayout`] is returned and the table should be loaded
+    /// with [`read_from`](Self::read_from).
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while the table, or any
+    /// reference to its entries, is alive.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn map_file<P: AsRef<std::path::Path>, M: AsMpz>(
+        path: P,
+        modulus: &M,
+    ) -> Result<Self> {
+        check_modulus(modulus)?;
+        let file = std::fs::File::open(path)?;
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let mut decoder = Decoder::new(MAGIC, &map)?;
+        let layout = Layout::decode(&mut decoder, modulus)?;
+        let entries = layout.entry_bytes(decoder)?;
+        if cfg!(target_endian = "big")
+            || layout.limb_bytes != size_of::<limb_t>()
+            || !entries.as_ptr().cast::<limb_t>().is_aligned()
+        {
+            return Err(Error::IncompatibleLayout);
+        }
+        let limbs = entries.as_ptr().cast::<limb_t>();
+        let mut values = Vec::with_capacity(layout.entries);
+        for i in 0..layout.entries {
+            let mut entry = MaybeUninit::uninit();
+            unsafe {
+                gmp::mpz_roinit_n(
+                    entry.as_mut_ptr(),
+                    limbs.add(i * layout.limbs),
+                    layout.limbs as _,
+                );
+                if gmp::mpz_cmp(entry.as_ptr(), layout.modulus.as_raw()) >= 0 {
+                    return Err(Error::InvalidFormat);
+                }
+                values.push(entry.assume_init());
+            }
+        }
+        let tabs_len = layout.len.div_ceil(layout.block_width.max(1));
+        let mut offset = 0;
+        let mut tabs: Vec<*mut mpz_t> = widths(layout.len, layout.block_width, tabs_len)
+            .map(|w| {
+                let tab = unsafe { values.as_mut_ptr().add(offset) };
+                offset += 1 << w;
+                tab
+            })
+            .collect();
+        let raw = gmpmee_spowm_tab {
+            len: layout.len as _,
+            block_width: layout.block_width as _,
+            tabs_len: tabs_len as _,
+            tabs: tabs.as_mut_ptr(),
+            modulus: layout.modulus.into_raw(),
+        };
+        Ok(Self {
+            raw,
+            mapping: Some(Mapping {
+                _map: map,
+                _entries: values,
+                _tabs: tabs,
+            }),
+        })
+    }
+}
+
+impl Clone for SpowmTable {
+    fn clone(&self) -> Self {
+        Self::owned(unsafe { clone_raw(&self.raw) })
+    }
+}
+
+impl Drop for SpowmTable {
+    fn drop(&mut self) {
+        // The entries of a mapped table belong to the mapping, only the
+        // modulus was allocated by GMP.
+        #[cfg(feature = "mmap")]
+        if self.mapping.is_some() {
+            unsafe { gmp::mpz_clear(&mut self.raw.modulus) };
+            return;
+        }
+        unsafe { gmpmee_spowm_clear(&mut self.raw) }
+    }
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` with [`gmpmee_spowm`],
+/// writing the result into `rop`.
+///
+/// From [`BUCKET_THRESHOLD`] bases on, the product is computed with
+/// [`spowm_bucket`] instead, with the window of [`bucket_window`], if it takes
+/// fewer multiplications for the length of the exponents, whereas
+/// [`gmpmee_spowm`] itself never switches. For two or
+/// three bases and an odd modulus, it is computed with the interleaved windows
+/// of [`spowm2`](crate::spowm2), see [`INTERLEAVED_MAX_LEN`]. Otherwise,
+/// if a profile was installed with [`set_tuning_profile`](crate::set_tuning_profile),
+/// the block width is taken from it, see [`TuningProfile::spowm`](crate::TuningProfile::spowm).
+///
+/// The number of exponents must be the number of bases and the modulus must be
+/// greater than one. A negative exponent inverts its base: the bases with
+/// negative exponents are raised to the absolute values of their exponents in
+/// a product of their own, which is inverted once at the end. If it has no
+/// inverse, i.e. if one of these bases is not invertible modulo the modulus,
+/// [`Error::NotInvertible`] is returned.
+pub fn spowm<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+) -> Result<()> {
+    if has_negative(exponents) {
+        return spowm_signed(rop, bases, exponents, modulus, |rop, bases, exponents| {
+            spowm(rop, bases, exponents, modulus)
+        });
+    }
+    if bases.len() >= BUCKET_THRESHOLD {
+        let bits = max_bitlen(exponents);
+        if bucket_is_cheaper(bases.len(), bits, spowm_block_width(modulus, bits)) {
+            let window = bucket_window(bases.len(), bits);
+            return spowm_bucket(rop, bases, exponents, modulus, window);
+        }
+    }
+    if (2..=INTERLEAVED_MAX_LEN).contains(&bases.len())
+        && unsafe { gmp::mpz_odd_p(modulus.as_raw()) } != 0
+    {
+        return spowm_small(rop, bases, exponents, modulus);
+    }
+    if let Some(profile) = tuning_profile() {
+        return profile.spowm(rop, bases, exponents, modulus);
+    }
+    check_modulus(modulus)?;
+    check_len(bases.len(), exponents.len())?;
+    unsafe {
+        gmpmee_spowm(
+            rop.as_raw_mut(),
+            slice_as_raw(bases),
+            slice_as_raw(exponents),
+            bases.len() as _,
+            modulus.as_raw(),
+        )
+    };
+    Ok(())
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` with
+/// [`gmpmee_spowm_naive`], one exponentiation at a time, writing the result into
+/// `rop`.
+///
+/// Slower than [`spowm()`], it serves as a reference for the other
+/// implementations. The parameters are checked as in [`spowm()`].
+pub fn spowm_naive<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+) -> Result<()> {
+    if has_negative(exponents) {
+        return spowm_signed(rop, bases, exponents, modulus, |rop, bases, exponents| {
+            spowm_naive(rop, bases, exponents, modulus)
+        });
+    }
+    check_modulus(modulus)?;
+    check_len(bases.len(), exponents.len())?;
+    unsafe {
+        gmpmee_spowm_naive(
+            rop.as_raw_mut(),
+            slice_as_raw(bases),
+            slice_as_raw(exponents),
+            bases.len() as _,
+            modulus.as_raw(),
+        )
+    };
+    Ok(())
+}
+
+/// Block width and batch length of a simultaneous exponentiation.
+///
+/// The bases are processed in batches of `batch_len`, each with its own table of
+/// subtables for blocks of `block_width` bases. Only the table of one batch is
+/// held in memory at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockBatch {
+    block_width: usize,
+    batch_len: usize,
+}
+
+impl BlockBatch {
+    /// Validates the parameters: the block width must lie in `1..=MAX_BLOCK_WIDTH`
+    /// and the batch length must not be zero.
+    pub fn new(block_width: usize, batch_len: usize) -> Result<Self> {
+        check_block_width(block_width)?;
+        if batch_len == 0 {
+            return Err(Error::InvalidBatchLen(batch_len));
+        }
+        Ok(Self {
+            block_width,
+            batch_len,
+        })
+    }
+
+    /// Chooses the largest batch length, in whole blocks, whose table fits in
+    /// `budget` bytes for a modulus of `modulus_bits` bits.
+    ///
+    /// The memory is estimated with [`block_bytes`](Self::block_bytes).
+    pub fn with_memory_budget(
+        modulus_bits: usize,
+        block_width: usize,
+        budget: usize,
+    ) -> Result<Self> {
+        check_block_width(block_width)?;
+        let required = Self::block_bytes(modulus_bits, block_width);
+        match budget / required {
+            0 => Err(Error::MemoryBudget { required, budget }),
+            blocks => Self::new(block_width, blocks * block_width),
+        }
+    }
+
+    /// Estimated number of bytes of one subtable.
+    ///
+    /// Each of the `2^block_width` entries is an `mpz_t` whose limbs were
+    /// allocated for the unreduced product of two residues, i.e. twice the
+    /// modulus size.
+    pub fn block_bytes(modulus_bits: usize, block_width: usize) -> usize {
+        let limbs = modulus_bits.div_ceil(gmp::LIMB_BITS as usize).max(1);
+        let entry = size_of::<mpz_t>() + 2 * limbs * size_of::<limb_t>();
+        (entry << block_width) + size_of::<*mut mpz_t>()
+    }
+
+    /// Estimated number of bytes of the table of one batch.
+    pub fn table_bytes(&self, modulus_bits: usize) -> usize {
+        self.batch_len.div_ceil(self.block_width)
+            * Self::block_bytes(modulus_bits, self.block_width)
+    }
+
+    /// Number of bases in each block.
+    pub fn block_width(&self) -> usize {
+        self.block_width
+    }
+
+    /// Number of bases in each batch.
+    pub fn batch_len(&self) -> usize {
+        self.batch_len
+    }
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` with
+/// [`gmpmee_spowm_block_batch`], writing the result into `rop`.
+///
+/// Same requirements as [`spowm`], with the block width and batch length given
+/// by `params`.
+pub fn spowm_block_batch<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+    params: BlockBatch,
+) -> Result<()> {
+    if has_negative(exponents) {
+        return spowm_signed(rop, bases, exponents, modulus, |rop, bases, exponents| {
+            spowm_block_batch(rop, bases, exponents, modulus, params)
+        });
+    }
+    check_modulus(modulus)?;
+    check_len(bases.len(), exponents.len())?;
+    unsafe {
+        gmpmee_spowm_block_batch(
+            rop.as_raw_mut(),
+            slice_as_raw(bases),
+            slice_as_raw(exponents),
+            bases.len() as _,
+            modulus.as_raw(),
+            params.block_width as _,
+            params.batch_len as _,
+        )
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Rng, product};
+
+    fn inputs(rng: &mut Rng, len: usize, bits: usize) -> (Vec<Mpz>, Vec<Mpz>, Mpz) {
+        let modulus = rng.odd_modulus(256);
+        let bases = (0..len).map(|_| rng.below(&modulus)).collect();
+        let exponents = (0..len).map(|_| rng.bits(bits)).collect();
+        (bases, exponents, modulus)
+    }
+
+    #[test]
+    fn table_matches_powm() {
+        let mut rng = Rng::new(1);
+        // A block width above the number of bases is lowered to it.
+        for (len, block_width) in [(1, 1), (5, 2), (7, 3), (16, 4), (9, 8), (3, 5)] {
+            let (bases, exponents, modulus) = inputs(&mut rng, len, 200);
+            let table = SpowmTable::new(&modulus, &bases, block_width).unwrap();
+            assert_eq!(table.len(), len);
+            assert_eq!(table.block_width(), block_width.min(len));
+            assert_eq!(table.modulus(), &modulus);
+            let expected = product(&bases, &exponents, &modulus);
+            assert_eq!(table.pow(&exponents).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn spowm_matches_naive() {
+        let mut rng = Rng::new(2);
+        for len in [0, 1, 2, 3, 4, 17, 100] {
+            let (bases, exponents, modulus) = inputs(&mut rng, len, 300);
+            let expected = product(&bases, &exponents, &modulus);
+            let (mut fast, mut naive, mut batched) = (Mpz::new(), Mpz::new(), Mpz::new());
+            spowm(&mut fast, &bases, &exponents, &modulus).unwrap();
+            spowm_naive(&mut naive, &bases, &exponents, &modulus).unwrap();
+            let params = BlockBatch::new(3, 10).unwrap();
+            spowm_block_batch(&mut batched, &bases, &exponents, &modulus, params).unwrap();
+            assert_eq!(naive, expected);
+            assert_eq!(fast, expected);
+            assert_eq!(batched, expected);
+        }
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let mut rng = Rng::new(3);
+        let (bases, exponents, modulus) = inputs(&mut rng, 4, 64);
+        let expected = Err(Error::LengthMismatch {
+            expected: 4,
+            found: 3,
+        });
+        let mut rop = Mpz::new();
+        assert_eq!(spowm(&mut rop, &bases, &exponents[..3], &modulus), expected);
+        assert_eq!(
+            spowm_naive(&mut rop, &bases, &exponents[..3], &modulus),
+            expected
+        );
+        let table = SpowmTable::new(&modulus, &bases, 2).unwrap();
+        assert_eq!(table.pow(&exponents[..3]), expected.map(|()| Mpz::new()));
+    }
+
+    #[test]
+    fn empty_table() {
+        let modulus = Mpz::from(101u32);
+        let table = SpowmTable::new(&modulus, &[] as &[Mpz], 4).unwrap();
+        assert!(table.is_empty());
+        assert_eq!(table.pow(&[] as &[Mpz]).unwrap(), Mpz::from(1u32));
+        let clone = table.clone();
+        assert!(clone.is_empty());
+        assert_eq!(clone.pow(&[] as &[Mpz]).unwrap(), Mpz::from(1u32));
+        let mut rop = Mpz::new();
+        spowm(&mut rop, &[] as &[Mpz], &[] as &[Mpz], &modulus).unwrap();
+        assert_eq!(rop, Mpz::from(1u32));
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let bases = [Mpz::from(2u32), Mpz::from(3u32)];
+        for block_width in [0, MAX_BLOCK_WIDTH + 1] {
+            assert_eq!(
+                SpowmTable::new(&Mpz::from(101u32), &bases, block_width).unwrap_err(),
+                Error::InvalidBlockWidth(block_width)
+            );
+        }
+        for modulus in [Mpz::new(), Mpz::from(1u32), Mpz::from(-7i32)] {
+            assert_eq!(
+                SpowmTable::new(&modulus, &bases, 2).unwrap_err(),
+                Error::InvalidModulus
+            );
+            let mut rop = Mpz::new();
+            assert_eq!(
+                spowm(&mut rop, &bases, &bases, &modulus),
+                Err(Error::InvalidModulus)
+            );
+        }
+        assert_eq!(BlockBatch::new(2, 0), Err(Error::InvalidBatchLen(0)));
+        assert_eq!(BlockBatch::new(0, 2), Err(Error::InvalidBlockWidth(0)));
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let mut rng = Rng::new(4);
+        let (bases, exponents, modulus) = inputs(&mut rng, 10, 128);
+        let table = SpowmTable::new(&modulus, &bases, 3).unwrap();
+        let expected = table.pow(&exponents).unwrap();
+        let clone = table.clone();
+        drop(table);
+        assert_eq!(clone.len(), 10);
+        assert_eq!(clone.block_width(), 3);
+        assert_eq!(clone.pow(&exponents).unwrap(), expected);
+    }
+
+    #[test]
+    fn tables_are_sync() {
+        fn shared<T: Send + Sync>() {}
+        shared::<SpowmTable>();
+        shared::<crate::CombTable>();
+        shared::<crate::MultiFpowmTable>();
+    }
+
+    /// Serializes a table with the given header and `entries` zero entries.
+    fn forged(
+        modulus: &Mpz,
+        len: usize,
+        block_width: usize,
+        tabs_len: usize,
+        entries: usize,
+    ) -> Vec<u8> {
+        let limbs = unsafe { gmp::mpz_size(modulus.as_raw()) };
+        let mut encoder = Encoder::new(MAGIC);
+        encoder.u64(gmp::LIMB_BITS as usize);
+        encoder.u64(len);
+        encoder.u64(block_width);
+        encoder.u64(tabs_len);
+        encoder.mpz(modulus);
+        encoder.u64(limbs);
+        encoder.align(8);
+        for _ in 0..entries {
+            encoder.limbs(&Mpz::new(), limbs);
+        }
+        encoder.finish()
+    }
+
+    #[test]
+    fn entry_count_matches_widths() {
+        for len in 0..40 {
+            for block_width in 1..=MAX_BLOCK_WIDTH.min(len) {
+                let expected = widths(len, block_width, len.div_ceil(block_width))
+                    .map(|w| 1usize << w)
+                    .sum();
+                assert_eq!(entry_count(len, block_width), Some(expected));
+            }
+        }
+        assert_eq!(entry_count(usize::MAX, MAX_BLOCK_WIDTH), None);
+    }
+
+    #[test]
+    fn from_bytes_rejects_forged_headers() {
+        let modulus = Rng::new(7).odd_modulus(128);
+        // Five bases in blocks of two: 4 + 4 + 2 entries.
+        assert!(SpowmTable::from_bytes(&forged(&modulus, 5, 2, 3, 10), &modulus).is_ok());
+        for entries in [0, 9, 11] {
+            assert_eq!(
+                SpowmTable::from_bytes(&forged(&modulus, 5, 2, 3, entries), &modulus).unwrap_err(),
+                Error::InvalidFormat
+            );
+        }
+        // Headers announcing more blocks than could ever be stored, or whose
+        // number of entries overflows, are rejected without reading further.
+        let huge = 1 << 40;
+        let max = usize::MAX;
+        for (len, block_width, tabs_len) in [
+            (huge, 1, huge),
+            (max, MAX_BLOCK_WIDTH, max.div_ceil(MAX_BLOCK_WIDTH)),
+            (max, 1, max),
+        ] {
+            assert_eq!(
+                SpowmTable::from_bytes(&forged(&modulus, len, block_width, tabs_len, 0), &modulus)
+                    .unwrap_err(),
+                Error::InvalidFormat
+            );
+        }
+    }
+
+    #[test]
+    fn from_bytes_rejects_truncated() {
+        let mut rng = Rng::new(8);
+        let (bases, exponents, modulus) = inputs(&mut rng, 5, 64);
+        let table = SpowmTable::new(&modulus, &bases, 2).unwrap();
+        let bytes = table.to_bytes();
+        let loaded = SpowmTable::from_bytes(&bytes, &modulus).unwrap();
+        assert_eq!(loaded.pow(&exponents), table.pow(&exponents));
+        for len in 0..bytes.len() {
+            assert!(SpowmTable::from_bytes(&bytes[..len], &modulus).is_err());
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_new_matches_new() {
+        let mut rng = Rng::new(14);
+        // Lengths that are not multiples of the block width leave a shorter
+        // last block.
+        for (len, block_width) in [(0, 2), (1, 1), (7, 3), (16, 4), (21, 8), (3, 5)] {
+            let (bases, _, modulus) = inputs(&mut rng, len, 0);
+            let table = SpowmTable::new(&modulus, &bases, block_width).unwrap();
+            let par = SpowmTable::par_new(&modulus, &bases, block_width).unwrap();
+            assert_eq!(par.to_bytes(), table.to_bytes(), "{len} {block_width}");
+        }
+    }
+}
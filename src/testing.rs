@@ -0,0 +1,120 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+//! Helpers shared by the unit tests.
+
+use crate::mpz::{AsMpz, Mpz};
+use crate::prime::RandSource;
+use gmp_mpfr_sys::gmp::{self, randstate_ptr, randstate_t};
+use std::mem::MaybeUninit;
+
+/// Seeded GMP random state, so that failures can be reproduced.
+pub(crate) struct Rng(randstate_t);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        unsafe {
+            let mut state = MaybeUninit::uninit();
+            gmp::randinit_default(state.as_mut_ptr());
+            let mut state = state.assume_init();
+            gmp::randseed_ui(&mut state, seed as _);
+            Self(state)
+        }
+    }
+
+    /// Uniform integer of at most `bits` bits.
+    pub(crate) fn bits(&mut self, bits: usize) -> Mpz {
+        let mut z = Mpz::new();
+        unsafe { gmp::mpz_urandomb(z.as_raw_mut(), &mut self.0, bits as _) };
+        z
+    }
+
+    /// Uniform integer of exactly `bits` bits.
+    pub(crate) fn exact_bits(&mut self, bits: usize) -> Mpz {
+        let mut z = self.bits(bits);
+        unsafe { gmp::mpz_setbit(z.as_raw_mut(), (bits - 1) as _) };
+        z
+    }
+
+    /// Uniform integer in `0..bound`.
+    pub(crate) fn below(&mut self, bound: &Mpz) -> Mpz {
+        let mut z = Mpz::new();
+        unsafe { gmp::mpz_urandomm(z.as_raw_mut(), &mut self.0, bound.as_raw()) };
+        z
+    }
+
+    /// Odd integer of exactly `bits` bits.
+    pub(crate) fn odd_modulus(&mut self, bits: usize) -> Mpz {
+        let mut z = self.exact_bits(bits);
+        unsafe { gmp::mpz_setbit(z.as_raw_mut(), 0) };
+        z
+    }
+
+    /// Prime of at least `bits` bits.
+    pub(crate) fn prime(&mut self, bits: usize) -> Mpz {
+        let mut z = self.odd_modulus(bits);
+        unsafe { gmp::mpz_nextprime(z.as_raw_mut(), z.as_raw()) };
+        z
+    }
+}
+
+unsafe impl RandSource for Rng {
+    fn as_raw_mut(&mut self) -> randstate_ptr {
+        &mut self.0
+    }
+}
+
+impl Drop for Rng {
+    fn drop(&mut self) {
+        unsafe { gmp::randclear(&mut self.0) }
+    }
+}
+
+/// Returns `-op`.
+pub(crate) fn neg(op: &Mpz) -> Mpz {
+    let mut z = Mpz::new();
+    unsafe { gmp::mpz_neg(z.as_raw_mut(), op.as_raw()) };
+    z
+}
+
+/// Returns `base^exponent` modulo `modulus` with `mpz_powm`, which inverts the
+/// base for a negative exponent.
+pub(crate) fn powm(base: &Mpz, exponent: &Mpz, modulus: &Mpz) -> Mpz {
+    let mut z = Mpz::new();
+    unsafe {
+        gmp::mpz_powm(
+            z.as_raw_mut(),
+            base.as_raw(),
+            exponent.as_raw(),
+            modulus.as_raw(),
+        )
+    };
+    z
+}
+
+/// Returns the product of the `b_i^e_i` modulo `modulus`, one `mpz_powm` at a
+/// time.
+pub(crate) fn product(bases: &[Mpz], exponents: &[Mpz], modulus: &Mpz) -> Mpz {
+    let mut z = Mpz::from(1u32);
+    for (base, exponent) in bases.iter().zip(exponents) {
+        let power = powm(base, exponent, modulus);
+        unsafe {
+            gmp::mpz_mul(z.as_raw_mut(), z.as_raw(), power.as_raw());
+            gmp::mpz_mod(z.as_raw_mut(), z.as_raw(), modulus.as_raw());
+        }
+    }
+    z
+}
@@ -0,0 +1,584 @@
+// Copyright © 2024 Denis Morel
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Lesser General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Lesser General Public License and
+// a copy of the GNU General Public License along with this program. If not, see
+// <https://www.gnu.org/licenses/>.
+
+use crate::array::MpzArray;
+use crate::error::{Error, Result};
+use crate::gmpmee::gmpmee_spowm_block_batch;
+use crate::mpz::{AsMpz, Mpz, slice_as_raw};
+use crate::prime::RandSource;
+use crate::serial::{Decoder, Encoder};
+use crate::spowm::{
+    BlockBatch, MAX_BLOCK_WIDTH, check_block_width, check_modulus, max_bitlen, spowm_block_batch,
+};
+use gmp_mpfr_sys::gmp;
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"GMPMEETP";
+
+/// Profile installed with [`set_tuning_profile`].
+static PROFILE: RwLock<Option<Arc<TuningProfile>>> = RwLock::new(None);
+
+/// Block width minimizing `2^w - w - 1` multiplications for the precomputation
+/// of a block and `exponent_bitlen / w` for the computation, as
+/// `theoretical_block_width` in `spowm.c`.
+pub(crate) fn theoretical_block_width(exponent_bitlen: usize) -> usize {
+    let mut optimum = (exponent_bitlen, 1);
+    for w in 2..50 {
+        let estimate = (1 << w) - w - 1 + exponent_bitlen / w;
+        if estimate > optimum.0 {
+            break;
+        }
+        optimum = (estimate, w);
+    }
+    optimum.1
+}
+
+/// Block widths for the moduli of at least `modulus_bitlen` bits, up to the
+/// next row of a [`TuningProfile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TuningRow {
+    /// Smallest modulus bit length of the row.
+    pub modulus_bitlen: usize,
+    /// Pairs of an exponent bit length and the block width used from it on, by
+    /// increasing exponent bit length. Exponents shorter than the first use
+    /// the first block width.
+    pub block_widths: Vec<(usize, usize)>,
+    /// Exponent bit length from which the theoretical block width plus two is
+    /// used instead, as `gmpmee_spowm` does beyond its table.
+    pub theoretical_from: Option<usize>,
+}
+
+/// Block widths chosen by [`spowm()`](crate::spowm()) from the bit lengths of
+/// the modulus and of the longest exponent.
+///
+/// [`builtin`](Self::builtin) is the `best_block_widths` table of `spowm.c`,
+/// used by [`gmpmee_spowm`](crate::gmpmee_spowm). [`TuningGrid::tune`] times
+/// the candidates on the current machine and returns a profile of its own,
+/// which [`set_tuning_profile`] installs in place of the built-in table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TuningProfile {
+    rows: Vec<TuningRow>,
+}
+
+impl TuningProfile {
+    /// Validates the rows: there must be at least one, by increasing modulus
+    /// bit length, each with at least one block width, by increasing exponent
+    /// bit length, and the block widths must lie in `1..=MAX_BLOCK_WIDTH`.
+    pub fn new(rows: Vec<TuningRow>) -> Result<Self> {
+        let increasing = |a: usize, b: usize| a < b;
+        let valid = !rows.is_empty()
+            && rows
+                .windows(2)
+                .all(|r| increasing(r[0].modulus_bitlen, r[1].modulus_bitlen))
+            && rows.iter().all(|row| {
+                !row.block_widths.is_empty()
+                    && row
+                        .block_widths
+                        .windows(2)
+                        .all(|w| increasing(w[0].0, w[1].0))
+                    && row
+                        .block_widths
+                        .iter()
+                        .all(|&(_, w)| check_block_width(w).is_ok())
+            });
+        match valid {
+            true => Ok(Self { rows }),
+            false => Err(Error::InvalidProfile),
+        }
+    }
+
+    /// The table of `spowm.c`, with the same choices as `gmpmee_spowm`.
+    ///
+    /// Each row of thresholds `t` selects the block width `5 + k` for exponent
+    /// bit lengths in `[t[k], t[k + 1])`, and five below `t[1]`. From the last
+    /// threshold on, the theoretical block width plus two is used.
+    pub fn builtin() -> Self {
+        let row = |modulus_bitlen, t: &[usize]| TuningRow {
+            modulus_bitlen,
+            block_widths: std::iter::once((0, 5))
+                .chain(
+                    (1..t.len() - 1)
+                        .filter(|&k| t[k] < t[k + 1])
+                        .map(|k| (t[k], 5 + k)),
+                )
+                .collect(),
+            theoretical_from: t.last().copied(),
+        };
+        Self {
+            rows: vec![
+                row(64, &[100, 150, 350, 1100, 1100]),
+                row(128, &[100, 150, 350, 1000, 1350]),
+                row(256, &[100, 150, 450, 4450]),
+                row(512, &[100, 200, 500, 1700, 5000]),
+                row(1024, &[100, 100, 500, 1000, 2500, 6000]),
+                row(2048, &[100, 150, 450, 1000, 2000, 4500, 8200]),
+                row(4096, &[100, 200, 350, 900, 2000, 4400, 7300]),
+            ],
+        }
+    }
+
+    /// Rows of the profile, by increasing modulus bit length.
+    pub fn rows(&self) -> &[TuningRow] {
+        &self.rows
+    }
+
+    /// Returns the block width for a modulus of `modulus_bitlen` bits and
+    /// exponents of at most `exponent_bitlen` bits.
+    ///
+    /// The row is the last one whose modulus bit length does not exceed
+    /// `modulus_bitlen`, or the first one for smaller moduli.
+    pub fn block_width(&self, modulus_bitlen: usize, exponent_bitlen: usize) -> usize {
+        let row = self
+            .rows
+            .iter()
+            .rev()
+            .find(|row| row.modulus_bitlen <= modulus_bitlen)
+            .unwrap_or(&self.rows[0]);
+        if row
+            .theoretical_from
+            .is_some_and(|bitlen| exponent_bitlen >= bitlen)
+        {
+            return (theoretical_block_width(exponent_bitlen) + 2).min(MAX_BLOCK_WIDTH);
+        }
+        row.block_widths
+            .iter()
+            .rev()
+            .find(|&&(bitlen, _)| bitlen <= exponent_bitlen)
+            .unwrap_or(&row.block_widths[0])
+            .1
+    }
+
+    /// Computes the product of the `b_i^e_i` modulo `modulus` in a single
+    /// batch, with the block width of the profile, writing the result into
+    /// `rop`.
+    ///
+    /// Same requirements as [`spowm()`](crate::spowm()).
+    pub fn spowm<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+        &self,
+        rop: &mut R,
+        bases: &[B],
+        exponents: &[E],
+        modulus: &M,
+    ) -> Result<()> {
+        check_modulus(modulus)?;
+        let block_width = self.block_width(modulus_bitlen(modulus), max_bitlen(exponents));
+        spowm_block_batch(
+            rop,
+            bases,
+            exponents,
+            modulus,
+            effective(block_width, bases.len(), bases.len())?,
+        )
+    }
+
+    /// Serializes the profile in a versioned and checksummed binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new(MAGIC);
+        encoder.u64(self.rows.len());
+        for row in &self.rows {
+            encoder.u64(row.modulus_bitlen);
+            match row.theoretical_from {
+                Some(bitlen) => {
+                    encoder.u64(1);
+                    encoder.u64(bitlen);
+                }
+                None => encoder.u64(0),
+            }
+            encoder.u64(row.block_widths.len());
+            for &(bitlen, width) in &row.block_widths {
+                encoder.u64(bitlen);
+                encoder.u64(width);
+            }
+        }
+        encoder.finish()
+    }
+
+    /// Writes the serialized profile to `writer`, see
+    /// [`to_bytes`](Self::to_bytes).
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Loads a profile serialized with [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder::new(MAGIC, bytes)?;
+        let mut rows = Vec::new();
+        for _ in 0..decoder.u64()? {
+            let modulus_bitlen = decoder.u64()?;
+            let theoretical_from = match decoder.u64()? {
+                0 => None,
+                1 => Some(decoder.u64()?),
+                _ => return Err(Error::InvalidFormat),
+            };
+            let mut block_widths = Vec::new();
+            for _ in 0..decoder.u64()? {
+                block_widths.push((decoder.u64()?, decoder.u64()?));
+            }
+            rows.push(TuningRow {
+                modulus_bitlen,
+                block_widths,
+                theoretical_from,
+            });
+        }
+        decoder.finish()?;
+        Self::new(rows).map_err(|_| Error::InvalidFormat)
+    }
+
+    /// Reads a serialized profile from `reader` to its end, see
+    /// [`from_bytes`](Self::from_bytes).
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Installs `profile` for [`spowm()`](crate::spowm()) in the whole process, or
+/// restores [`gmpmee_spowm`](crate::gmpmee_spowm) and its built-in table with
+/// `None`.
+///
+/// Only the Rust [`spowm()`](crate::spowm()) and its wrappers read the profile.
+/// [`gmpmee_spowm`](crate::gmpmee_spowm) keeps its compiled-in table, and the
+/// functions taking an explicit block width, such as
+/// [`SpowmTable::new`](crate::SpowmTable::new) and
+/// [`spowm_block_batch`](crate::spowm_block_batch), use that width as given:
+/// pass them [`TuningProfile::block_width`] to apply the profile.
+pub fn set_tuning_profile(profile: Option<TuningProfile>) {
+    let mut installed = PROFILE.write().unwrap_or_else(|e| e.into_inner());
+    *installed = profile.map(Arc::new);
+}
+
+/// Returns the profile installed with [`set_tuning_profile`], if any.
+pub fn tuning_profile() -> Option<Arc<TuningProfile>> {
+    PROFILE.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Block width of the block method of [`spowm()`](crate::spowm()), from the
+/// installed profile or else from the built-in one, as `gmpmee_spowm`.
+pub(crate) fn spowm_block_width<M: AsMpz>(modulus: &M, exponent_bitlen: usize) -> usize {
+    let modulus_bitlen = modulus_bitlen(modulus);
+    match tuning_profile() {
+        Some(profile) => profile.block_width(modulus_bitlen, exponent_bitlen),
+        None => TuningProfile::builtin().block_width(modulus_bitlen, exponent_bitlen),
+    }
+}
+
+fn modulus_bitlen<M: AsMpz>(modulus: &M) -> usize {
+    unsafe { gmp::mpz_sizeinbase(modulus.as_raw(), 2) }
+}
+
+/// Returns the parameters the C functions actually use for `len` bases: the
+/// batch length is at most `len`, and the block width at most the batch
+/// length. An empty product gets a block width and batch length of one.
+fn effective(block_width: usize, batch_len: usize, len: usize) -> Result<BlockBatch> {
+    check_block_width(block_width)?;
+    if batch_len == 0 && len > 0 {
+        return Err(Error::InvalidBatchLen(batch_len));
+    }
+    let batch_len = batch_len.min(len).max(1);
+    BlockBatch::new(block_width.min(batch_len), batch_len)
+}
+
+/// Block-width choice of a simultaneous exponentiation, for
+/// [`spowm_with_strategy`].
+pub enum SpowmStrategy {
+    /// The table of `spowm.c`, as [`gmpmee_spowm`](crate::gmpmee_spowm), see
+    /// [`TuningProfile::builtin`].
+    Builtin,
+    /// The block widths of a profile, in a single batch.
+    Profile(Arc<TuningProfile>),
+    /// The block width minimizing the estimated number of multiplications, as
+    /// `theoretical_block_width` in `spowm.c`, in a single batch.
+    Theoretical,
+    /// A fixed block width, in a single batch.
+    Fixed(usize),
+    /// A function of the bit length of the modulus, the largest bit length of
+    /// the exponents and the number of bases, returning the block width and
+    /// the batch length.
+    Custom(Box<dyn Fn(usize, usize, usize) -> (usize, usize) + Send + Sync>),
+}
+
+impl SpowmStrategy {
+    /// Returns the parameters chosen for a modulus of `modulus_bitlen` bits,
+    /// exponents of at most `exponent_bitlen` bits and `len` bases.
+    ///
+    /// The parameters are those the computation actually uses: the batch
+    /// length is at most `len` and the block width at most the batch length.
+    /// A fixed or custom block width must lie in `1..=MAX_BLOCK_WIDTH` and a
+    /// custom batch length must not be zero.
+    pub fn params(
+        &self,
+        modulus_bitlen: usize,
+        exponent_bitlen: usize,
+        len: usize,
+    ) -> Result<BlockBatch> {
+        let (block_width, batch_len) = match self {
+            SpowmStrategy::Builtin => (
+                TuningProfile::builtin().block_width(modulus_bitlen, exponent_bitlen),
+                len,
+            ),
+            SpowmStrategy::Profile(profile) => {
+                (profile.block_width(modulus_bitlen, exponent_bitlen), len)
+            }
+            SpowmStrategy::Theoretical => (
+                theoretical_block_width(exponent_bitlen).min(MAX_BLOCK_WIDTH),
+                len,
+            ),
+            SpowmStrategy::Fixed(block_width) => (*block_width, len),
+            SpowmStrategy::Custom(choose) => choose(modulus_bitlen, exponent_bitlen, len),
+        };
+        effective(block_width, batch_len, len)
+    }
+}
+
+impl std::fmt::Debug for SpowmStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpowmStrategy::Builtin => write!(f, "Builtin"),
+            SpowmStrategy::Profile(profile) => f.debug_tuple("Profile").field(profile).finish(),
+            SpowmStrategy::Theoretical => write!(f, "Theoretical"),
+            SpowmStrategy::Fixed(block_width) => f.debug_tuple("Fixed").field(block_width).finish(),
+            SpowmStrategy::Custom(_) => f.debug_tuple("Custom").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Computes the product of the `b_i^e_i` modulo `modulus` with the parameters
+/// chosen by `strategy`, writing the result into `rop`, and returns these
+/// parameters.
+///
+/// Same requirements as [`spowm()`](crate::spowm()). The computation is that
+/// of [`spowm_block_batch`] with the parameters returned by
+/// [`SpowmStrategy::params`].
+pub fn spowm_with_strategy<R: AsMpz, B: AsMpz, E: AsMpz, M: AsMpz>(
+    rop: &mut R,
+    bases: &[B],
+    exponents: &[E],
+    modulus: &M,
+    strategy: &SpowmStrategy,
+) -> Result<BlockBatch> {
+    check_modulus(modulus)?;
+    let params = strategy.params(modulus_bitlen(modulus), max_bitlen(exponents), bases.len())?;
+    spowm_block_batch(rop, bases, exponents, modulus, params)?;
+    Ok(params)
+}
+
+/// Parameters timed by [`tune`](Self::tune).
+///
+/// For each modulus and exponent bit length, every block width is timed on
+/// vectors of each length, in a single batch, and the block width with the
+/// smallest total time per base wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TuningGrid {
+    /// Modulus bit lengths, one row of the profile each, increasing.
+    pub modulus_bitlens: Vec<usize>,
+    /// Exponent bit lengths, increasing.
+    pub exponent_bitlens: Vec<usize>,
+    /// Numbers of bases.
+    pub lens: Vec<usize>,
+    /// Candidate block widths.
+    pub block_widths: RangeInclusive<usize>,
+    /// Minimum duration of the repeated computations of each timing.
+    pub min_time: Duration,
+}
+
+impl Default for TuningGrid {
+    /// The moduli of 512 to 4096 bits, exponents of 64 to 2048 bits and
+    /// vectors of 50 and 200 bases, with block widths from 1 to 10.
+    fn default() -> Self {
+        Self {
+            modulus_bitlens: vec![512, 1024, 2048, 3072, 4096],
+            exponent_bitlens: vec![64, 128, 256, 512, 1024, 2048],
+            lens: vec![50, 200],
+            block_widths: 1..=10,
+            min_time: Duration::from_millis(10),
+        }
+    }
+}
+
+impl TuningGrid {
+    /// Times spowm over the grid on the current machine, with random moduli,
+    /// bases and exponents drawn from `rand`, and returns the resulting
+    /// profile.
+    ///
+    /// The bit lengths must be increasing, the moduli of at least two bits and
+    /// the exponents of at least one bit, the lengths must not be zero, and the
+    /// block widths must lie in `1..=MAX_BLOCK_WIDTH`. Each row lists the best
+    /// block width from the smallest exponent bit length on, and then each time
+    /// it changes.
+    pub fn tune<R: RandSource>(&self, rand: &mut R) -> Result<TuningProfile> {
+        let increasing = |v: &[usize]| !v.is_empty() && v.windows(2).all(|w| w[0] < w[1]);
+        if !increasing(&self.modulus_bitlens)
+            || !increasing(&self.exponent_bitlens)
+            || self.exponent_bitlens[0] == 0
+            || self.lens.is_empty()
+            || self.block_widths.is_empty()
+        {
+            return Err(Error::InvalidProfile);
+        }
+        if self.modulus_bitlens[0] < 2 {
+            return Err(Error::InvalidModulus);
+        }
+        if let Some(&len) = self.lens.iter().find(|&&len| len == 0) {
+            return Err(Error::InvalidBatchLen(len));
+        }
+        check_block_width(*self.block_widths.start())?;
+        check_block_width(*self.block_widths.end())?;
+        let max_len = self.lens.iter().copied().max().unwrap_or(0);
+        let rows = self
+            .modulus_bitlens
+            .iter()
+            .map(|&modulus_bitlen| {
+                let mut modulus = [Mpz::new()];
+                random_bits(&mut modulus, rand, modulus_bitlen);
+                unsafe { gmp::mpz_setbit(modulus[0].as_raw_mut(), 0) };
+                let mut bases = MpzArray::new(max_len);
+                bases.urandomb(rand, modulus_bitlen - 1);
+                let mut block_widths: Vec<(usize, usize)> = Vec::new();
+                for &exponent_bitlen in &self.exponent_bitlens {
+                    let mut exponents = MpzArray::new(max_len);
+                    random_bits(&mut exponents, rand, exponent_bitlen);
+                    let best = self
+                        .block_widths
+                        .clone()
+                        .map(|w| {
+                            let time: f64 = self
+                                .lens
+                                .iter()
+                                .map(|&len| {
+                                    self.time(&bases[..len], &exponents[..len], &modulus[0], w)
+                                        / len as f64
+                                })
+                                .sum();
+                            (time, w)
+                        })
+                        .min_by(|a, b| a.0.total_cmp(&b.0))
+                        .map_or(1, |(_, w)| w);
+                    if block_widths.last().is_none_or(|&(_, w)| w != best) {
+                        block_widths.push((exponent_bitlen, best));
+                    }
+                }
+                TuningRow {
+                    modulus_bitlen,
+                    block_widths,
+                    theoretical_from: None,
+                }
+            })
+            .collect();
+        TuningProfile::new(rows)
+    }
+
+    /// Returns the mean time in seconds of a simultaneous exponentiation.
+    fn time(&self, bases: &[Mpz], exponents: &[Mpz], modulus: &Mpz, block_width: usize) -> f64 {
+        let mut rop = Mpz::new();
+        let mut run = || unsafe {
+            gmpmee_spowm_block_batch(
+                rop.as_raw_mut(),
+                slice_as_raw(bases),
+                slice_as_raw(exponents),
+                bases.len() as _,
+                modulus.as_raw(),
+                block_width as _,
+                bases.len() as _,
+            )
+        };
+        run();
+        let start = Instant::now();
+        let mut count = 0u32;
+        while count == 0 || start.elapsed() < self.min_time {
+            run();
+            count += 1;
+        }
+        start.elapsed().as_secs_f64() / count as f64
+    }
+}
+
+/// Sets each integer of `rop` to a random integer of exactly `bits` bits.
+fn random_bits<R: RandSource>(rop: &mut [Mpz], rand: &mut R, bits: usize) {
+    for z in rop {
+        unsafe {
+            gmp::mpz_urandomb(z.as_raw_mut(), rand.as_raw_mut(), (bits - 1) as _);
+            gmp::mpz_setbit(z.as_raw_mut(), (bits - 1) as _);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Rng;
+
+    fn small_grid() -> TuningGrid {
+        TuningGrid {
+            modulus_bitlens: vec![64],
+            exponent_bitlens: vec![1, 16],
+            lens: vec![3],
+            block_widths: 1..=2,
+            min_time: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn tune_small_grid() {
+        let profile = small_grid().tune(&mut Rng::new(18)).unwrap();
+        assert_eq!(profile.rows().len(), 1);
+        assert_eq!(profile.rows()[0].modulus_bitlen, 64);
+        assert_eq!(profile.rows()[0].block_widths[0].0, 1);
+    }
+
+    #[test]
+    fn tune_rejects_invalid_grids() {
+        let mut rng = Rng::new(19);
+        let grids = [
+            TuningGrid {
+                exponent_bitlens: vec![0, 16],
+                ..small_grid()
+            },
+            TuningGrid {
+                exponent_bitlens: vec![0],
+                ..small_grid()
+            },
+            TuningGrid {
+                exponent_bitlens: vec![],
+                ..small_grid()
+            },
+            TuningGrid {
+                exponent_bitlens: vec![16, 1],
+                ..small_grid()
+            },
+            TuningGrid {
+                modulus_bitlens: vec![],
+                ..small_grid()
+            },
+            TuningGrid {
+                lens: vec![],
+                ..small_grid()
+            },
+        ];
+        for grid in grids {
+            assert_eq!(grid.tune(&mut rng), Err(Error::InvalidProfile));
+        }
+        let grid = TuningGrid {
+            lens: vec![0],
+            ..small_grid()
+        };
+        assert_eq!(grid.tune(&mut rng), Err(Error::InvalidBatchLen(0)));
+    }
+}